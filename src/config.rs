@@ -1,6 +1,7 @@
 extern crate serde_yaml as yamls;
 extern crate xdg;
 
+use std::collections::HashMap;
 use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
@@ -36,6 +37,13 @@ impl Config {
             .collect::<Vec<String>>()
     }
 
+    pub fn get_misconfigured_directory_names(&self) -> Vec<String> {
+        subscription::misconfigured_directories(&self.subscriptions)
+            .into_iter()
+            .map(|i| self.subscriptions[i].name.clone())
+            .collect::<Vec<String>>()
+    }
+
     pub fn get_entry_counts(&self) -> Vec<u64> {
         self.subscriptions
             .clone()
@@ -44,6 +52,43 @@ impl Config {
             .collect::<Vec<u64>>()
     }
 
+    pub fn get_unlistened_counts(&self) -> Vec<usize> {
+        self.subscriptions
+            .iter()
+            .map(|s| s.unlistened_count())
+            .collect::<Vec<usize>>()
+    }
+
+    pub fn get_mime_breakdown(&self) -> HashMap<String, usize> {
+        let mut breakdown = HashMap::new();
+        for sub in &self.subscriptions {
+            for (mime, count) in sub.mime_breakdown() {
+                *breakdown.entry(mime).or_insert(0) += count;
+            }
+        }
+
+        breakdown
+    }
+
+    pub fn get_session_stats(&self) -> Vec<subscription::SessionStats> {
+        self.subscriptions
+            .iter()
+            .map(|s| s.session_stats())
+            .collect::<Vec<subscription::SessionStats>>()
+    }
+
+    // Subscriptions whose directories can't be read are left out.
+    pub fn get_total_disk_usage(&self) -> u64 {
+        self.subscriptions
+            .iter()
+            .filter_map(|s| s.disk_usage().ok())
+            .sum()
+    }
+
+    pub fn get_summary_report(&self) -> String {
+        subscription::summary_report(&self.subscriptions)
+    }
+
     pub fn get_highest_entry_count_sub(&self) -> subscription::Subscription {
         self.subscriptions
             .clone()
@@ -88,18 +133,10 @@ fn process_location(cache_location: Option<String>) -> String {
         return cache_location.unwrap();
     }
 
-    let xdg_dirs = xdg::BaseDirectories::with_prefix("puckfetcher").unwrap();
-
-    let op_cache_file = xdg_dirs.find_cache_file("puckcache");
-    if op_cache_file == None {
-        panic!("No puckfetcher cache available");
+    match subscription::default_cache_location() {
+        Some(location) => location,
+        None => panic!("No puckfetcher cache available"),
     }
-
-    let path = op_cache_file.unwrap();
-
-    let file_str = path.to_str().unwrap().to_string();
-
-    return file_str;
 }
 
 pub fn read_config() -> Option<Config> {
@@ -267,6 +304,18 @@ mod tests {
         assert_eq!(n, names);
     }
 
+    #[test]
+    fn test_get_misconfigured_directory_names() {
+        let subs = vec![
+            subscription::Subscription::new("testurl1", "testname1", None),
+            subscription::Subscription::new("testurl2", "testname2", Some("testdir")),
+        ];
+
+        let conf = setup_loaded_cache(Some("testcache7"), Some(subs));
+
+        assert_eq!(vec!["testname1"], conf.get_misconfigured_directory_names());
+    }
+
     #[test]
     fn test_get_entry_counts() {
         let conf = setup_loaded_cache(Some("testcache2"), None);
@@ -280,6 +329,123 @@ mod tests {
         assert_eq!(l_vec, latest_vec);
     }
 
+    #[test]
+    fn test_get_unlistened_counts() {
+        let mut sub1 = subscription::Subscription::new("testurl1", "testname1", None);
+        sub1.push_test_entry("testentry1", "testentryurl1", true);
+        sub1.push_test_entry("testentry2", "testentryurl2", true);
+        sub1.push_test_summary(1, "testentry1", true);
+
+        let mut sub2 = subscription::Subscription::new("testurl2", "testname2", None);
+        for i in 1..4 {
+            sub2.push_test_entry(&format!("testentry{}", i), "testentryurl", true);
+        }
+
+        let conf = setup_loaded_cache(Some("testcache5"), Some(vec![sub1, sub2]));
+
+        assert_eq!(vec![1, 3], conf.get_unlistened_counts());
+    }
+
+    #[test]
+    fn test_get_mime_breakdown() {
+        let mut sub1 = subscription::Subscription::new("testurl1", "testname1", None);
+        sub1.push_test_entry("testentry1", "https://example.com/ep1.mp3", false);
+        sub1.push_test_entry("testentry2", "https://example.com/ep2.mp3", false);
+
+        let mut sub2 = subscription::Subscription::new("testurl2", "testname2", None);
+        sub2.push_test_entry("testentry1", "https://example.com/ep1.MP3", false);
+        sub2.push_test_entry("testentry2", "https://example.com/ep2.m4a", false);
+
+        let conf = setup_loaded_cache(Some("testcache10"), Some(vec![sub1, sub2]));
+        let breakdown = conf.get_mime_breakdown();
+
+        assert_eq!(2, breakdown.len());
+        assert_eq!(3, breakdown["audio/mpeg"]);
+        assert_eq!(1, breakdown["audio/mp4"]);
+    }
+
+    #[test]
+    fn test_get_session_stats() {
+        let mut sub1 = subscription::Subscription::new("testurl1", "testname1", None);
+        sub1.push_test_summary(1, "testentry1", false);
+        sub1.push_test_summary(2, "testentry2", true);
+        sub1.push_test_summary(3, "testentry3", true);
+        let sub2 = subscription::Subscription::new("testurl2", "testname2", None);
+
+        let conf = setup_loaded_cache(Some("testcache8"), Some(vec![sub1, sub2]));
+
+        let stats = conf.get_session_stats();
+
+        assert_eq!(
+            vec![
+                subscription::SessionStats {
+                    this_session: 2,
+                    prior_sessions: 1,
+                },
+                subscription::SessionStats::default(),
+            ],
+            stats
+        );
+    }
+
+    #[test]
+    fn test_get_total_disk_usage() {
+        let dir1 = "tmp_total_disk_usage_test1";
+        let dir2 = "tmp_total_disk_usage_test2";
+        // A file where a directory should be can't be read, so it is skipped.
+        let not_dir = "tmp_total_disk_usage_test3";
+        fs::create_dir_all(dir1).unwrap();
+        fs::create_dir_all(dir2).unwrap();
+        File::create(Path::new(dir1).join("ep1.mp3"))
+            .unwrap()
+            .write_all(&[0; 100])
+            .unwrap();
+        File::create(Path::new(dir2).join("ep2.mp3"))
+            .unwrap()
+            .write_all(&[0; 50])
+            .unwrap();
+        File::create(not_dir).unwrap();
+
+        let mut sub1 = subscription::Subscription::new("testurl1", "testname1", Some(dir1));
+        sub1.push_test_entry("testentry1", "https://example.com/ep1.mp3", false);
+        let mut sub2 = subscription::Subscription::new("testurl2", "testname2", Some(dir2));
+        sub2.push_test_entry("testentry2", "https://example.com/ep2.mp3", false);
+        let mut sub3 = subscription::Subscription::new("testurl3", "testname3", Some(not_dir));
+        sub3.push_test_entry("testentry3", "https://example.com/ep3.mp3", false);
+
+        let conf = setup_loaded_cache(Some("testcache6"), Some(vec![sub1, sub2, sub3]));
+        let total = conf.get_total_disk_usage();
+
+        fs::remove_dir_all(dir1).unwrap();
+        fs::remove_dir_all(dir2).unwrap();
+        fs::remove_file(not_dir).unwrap();
+
+        assert_eq!(150, total);
+    }
+
+    #[test]
+    fn test_get_summary_report() {
+        let mut sub1 = subscription::Subscription::new("testurl1", "testname1", None);
+        sub1.push_test_entry("testentry1", "testentryurl1", false);
+        sub1.push_test_entry("testentry2", "testentryurl2", true);
+        let mut sub2 = subscription::Subscription::new("testurl2", "testname2", None);
+        for i in 1..4 {
+            sub2.push_test_entry(&format!("testentry{}", i), "testentryurl", true);
+        }
+
+        let conf = setup_loaded_cache(Some("testcache9"), Some(vec![sub1, sub2]));
+
+        let report = conf.get_summary_report();
+
+        let expected = vec![
+            "Name       Entries  Queued",
+            "testname1        2       1",
+            "testname2        3       3",
+            "Total            5       4",
+        ];
+        assert_eq!(expected, report.lines().collect::<Vec<&str>>());
+    }
+
     #[test]
     fn test_get_highest_entry_count_sub() {
         let sub1 = subscription::Subscription::new("testurl1", "testname1", None);
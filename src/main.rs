@@ -1,13 +1,10 @@
-#[macro_use]
-extern crate serde_derive;
-extern crate itertools;
-
 extern crate clap;
+extern crate libpodstats;
 
 use clap::{App, Arg};
 
-mod config;
-mod subscription;
+use libpodstats::config;
+
 mod util;
 
 fn main() {
@@ -41,6 +38,10 @@ fn main() {
     let mut conf = config::Config::new(conf_file);
     conf.load_cache();
 
+    for name in conf.get_misconfigured_directory_names() {
+        println!("Warning: {} has no download directory set.", name);
+    }
+
     let prompt = util::Prompt {};
 
     let mut menu_options = Vec::new();
@@ -50,6 +51,11 @@ fn main() {
     menu_options.push("Get name of sub with highest entry count.");
     menu_options.push("Get the earliest entry for each sub.");
     menu_options.push("Get the latest entry for each sub.");
+    menu_options.push("Get unlistened entry counts of subscriptions in cache.");
+    menu_options.push("Get total disk usage of subscriptions in cache.");
+    menu_options.push("Get listening session stats for each sub.");
+    menu_options.push("Get a summary report of subscriptions in cache.");
+    menu_options.push("Get entry counts by media type across subscriptions.");
 
     loop {
         let res = prompt.select_from_menu(&menu_options);
@@ -87,6 +93,31 @@ fn main() {
                             println!("{} latest entry name: {}", i, item);
                         }
                     }
+                    7 => {
+                        for (i, item) in conf.get_unlistened_counts().iter().enumerate() {
+                            println!("{} unlistened count: {}", i, item);
+                        }
+                    }
+                    8 => {
+                        let item = conf.get_total_disk_usage();
+                        println!("Total disk usage: {} bytes", item);
+                    }
+                    9 => {
+                        for (i, item) in conf.get_session_stats().iter().enumerate() {
+                            println!("{} listened: {}", i, item);
+                        }
+                    }
+                    10 => {
+                        print!("{}", conf.get_summary_report());
+                    }
+                    11 => {
+                        let mut breakdown =
+                            conf.get_mime_breakdown().into_iter().collect::<Vec<_>>();
+                        breakdown.sort();
+                        for (mime, count) in breakdown {
+                            println!("{}: {}", mime, count);
+                        }
+                    }
                     _ => println!("Given invalid option!"),
                 }
             }
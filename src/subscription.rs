@@ -1,13 +1,38 @@
+extern crate flate2;
+extern crate regex;
 extern crate rmp;
 extern crate rmp_serde as rmps;
+extern crate roxmltree;
 extern crate serde;
+extern crate unicode_normalization;
+extern crate xdg;
 
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::env;
 use std::error::Error;
 use std::fmt;
 use std::fs;
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+use self::flate2::read::GzDecoder;
+use self::flate2::write::GzEncoder;
+use self::flate2::Compression;
+use self::regex::Regex;
+use self::serde::de::IgnoredAny;
+use self::unicode_normalization::UnicodeNormalization;
+
+// Directory given to subscriptions created without one.
+const DEFAULT_DIRECTORY: &str = "fakedir";
+
+// Version written into subscription bundles.
+const BUNDLE_VERSION: u32 = 1;
+
+// Environment variable overriding where the puckfetcher cache is read from.
+pub const STATE_ENV_VAR: &str = "PODSTATS_STATE";
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
 pub struct Subscription {
@@ -39,6 +64,55 @@ impl Subscription {
         }
     }
 
+    pub fn new_checked(
+        url: &str,
+        name: &str,
+        directory: Option<&str>,
+    ) -> Result<Subscription, SubscriptionError> {
+        let feed_url = FeedUrl::parse(url)?;
+        Ok(Subscription::new(&String::from(feed_url), name, directory))
+    }
+
+    // Compare everything but feed state, so download history doesn't matter.
+    pub fn same_config(&self, other: &Subscription) -> bool {
+        self.url == other.url
+            && self.original_url == other.original_url
+            && self.name == other.name
+            && self.directory == other.directory
+            && self.backlog_limit == other.backlog_limit
+            && self.use_title_as_filename == other.use_title_as_filename
+    }
+
+    // Stored as (version, subscription), so later schemas can still read
+    // bundles written by older versions.
+    pub fn to_bundle(&self) -> Vec<u8> {
+        rmps::to_vec(&(BUNDLE_VERSION, self)).unwrap_or_default()
+    }
+
+    pub fn from_bundle(bytes: &[u8]) -> Result<Subscription, SubscriptionError> {
+        let (version, _): (u32, IgnoredAny) = rmps::from_slice(bytes).map_err(decode_error)?;
+        match version {
+            1 => {
+                let (_, sub): (u32, Subscription) =
+                    rmps::from_slice(bytes).map_err(decode_error)?;
+                Ok(sub)
+            }
+            v => Err(SubscriptionError::UnsupportedVersion(v)),
+        }
+    }
+
+    // Stable key derived from original_url, so it survives redirects. Uses
+    // FNV-1a rather than std's hasher, whose output may change between releases.
+    pub fn id(&self) -> String {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in normalize_url(&self.original_url).bytes() {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+
+        format!("{:016x}", hash)
+    }
+
     pub fn get_latest_entry_number(&self) -> u64 {
         self.feed_state.latest_entry_number
     }
@@ -56,6 +130,577 @@ impl Subscription {
             None => "".to_string(),
         }.clone();
     }
+
+    pub fn unlistened_count(&self) -> usize {
+        self.playback_order()
+            .iter()
+            .filter(|e| !self.is_listened(e))
+            .count()
+    }
+
+    // Titles can repeat, so a summary entry must match the number too.
+    fn is_listened(&self, entry: &EntryView) -> bool {
+        self.feed_state
+            .summary_queue
+            .iter()
+            .any(|s| s.number == entry.number && s.name == entry.title)
+    }
+
+    // Entries with a summary entry naming them at their number.
+    fn listened_entries(&self) -> Vec<EntryView> {
+        self.get_entries()
+            .into_iter()
+            .filter(|e| self.is_listened(e))
+            .collect::<Vec<EntryView>>()
+    }
+
+    // Entries whose file is on disk but that never made it into the summary,
+    // the same notion of "listened" unlistened_count uses.
+    pub fn downloaded_unlistened(&self) -> Result<Vec<EntryView>, SubscriptionError> {
+        let on_disk = self.files_on_disk()?;
+
+        Ok(self
+            .get_entries()
+            .into_iter()
+            .zip(&self.feed_state.entries)
+            .filter(|&(_, e)| self.is_on_disk(e, &on_disk))
+            .filter(|(v, _)| !self.is_listened(v))
+            .map(|(v, _)| v)
+            .collect::<Vec<EntryView>>())
+    }
+
+    // Drop queued entries whose file is already in the directory, say because
+    // it was copied in by hand. Returns how many left the queue.
+    pub fn import_existing_files(&mut self) -> Result<usize, SubscriptionError> {
+        let on_disk = self.files_on_disk()?;
+
+        let before = self.feed_state.queue.len();
+        let queue = self
+            .feed_state
+            .queue
+            .iter()
+            .filter(|e| !self.is_on_disk(e, &on_disk))
+            .cloned()
+            .collect::<Vec<Entry>>();
+        self.feed_state.queue = queue;
+
+        Ok(before - self.feed_state.queue.len())
+    }
+
+    // Queue every entry that isn't queued already, oldest first.
+    pub fn requeue_all(&mut self) {
+        for entry in self.feed_state.entries.iter().rev() {
+            if !self.feed_state.queue.contains(entry) {
+                self.feed_state.queue.push(entry.clone());
+            }
+        }
+    }
+
+    // Copy pointed at another directory, with every entry queued so the files
+    // get fetched again there.
+    pub fn clone_to_directory(&self, dir: &str) -> Subscription {
+        let mut sub = self.clone();
+        sub.directory = dir.to_string();
+        sub.requeue_all();
+
+        sub
+    }
+
+    // Collapse entries sharing a first URL into the oldest of them, keeping the
+    // most complete copy. Returns the number of entries removed.
+    pub fn dedupe_entries(&mut self) -> usize {
+        let entries = &self.feed_state.entries;
+        let count = entries.len();
+
+        // Walk oldest first, so each group starts with its lowest number.
+        let mut groups: Vec<(&str, Vec<usize>)> = Vec::new();
+        for i in (0..count).rev() {
+            if let Some(url) = entries[i].urls.first() {
+                match groups.iter().position(|g| g.0 == url) {
+                    Some(g) => groups[g].1.push(i),
+                    None => groups.push((url, vec![i])),
+                }
+            }
+        }
+
+        let score = |e: &Entry| e.urls.len() + !e.title.is_empty() as usize;
+
+        // For every index, the index of the entry that replaces it.
+        let mut kept_for: Vec<usize> = (0..count).collect();
+        let mut best_for: Vec<Option<Entry>> = vec![None; count];
+        for (_, indices) in groups.iter().filter(|g| g.1.len() > 1) {
+            let mut best = indices[0];
+            for &i in indices {
+                if score(&entries[i]) > score(&entries[best]) {
+                    best = i;
+                }
+                kept_for[i] = indices[0];
+            }
+            best_for[indices[0]] = Some(entries[best].clone());
+        }
+
+        let mut new_entries = Vec::new();
+        let mut new_positions = vec![0; count];
+        for (i, entry) in entries.iter().enumerate() {
+            if kept_for[i] != i {
+                continue;
+            }
+            new_positions[i] = new_entries.len();
+            new_entries.push(best_for[i].clone().unwrap_or_else(|| entry.clone()));
+        }
+
+        let removed = count - new_entries.len();
+        if removed == 0 {
+            return 0;
+        }
+
+        // Only follow a summary entry that names the entry at its number. Ones
+        // that already disagree are left alone for reconcile_summary.
+        let new_count = new_entries.len() as u64;
+        let renumber = |summary: &SummaryEntry| -> Option<u64> {
+            if summary.number == 0 || summary.number > count as u64 {
+                return None;
+            }
+            let old = count - summary.number as usize;
+            if entries[old].title != summary.name {
+                return None;
+            }
+            Some(new_count - new_positions[kept_for[old]] as u64)
+        };
+
+        let mut queue: Vec<Entry> = Vec::new();
+        for entry in &self.feed_state.queue {
+            let replacement = match entries.iter().position(|e| e == entry) {
+                Some(i) => new_entries[new_positions[kept_for[i]]].clone(),
+                None => entry.clone(),
+            };
+            if !queue.contains(&replacement) {
+                queue.push(replacement);
+            }
+        }
+
+        let mut summary_queue: Vec<SummaryEntry> = Vec::new();
+        for summary in &self.feed_state.summary_queue {
+            let fixed = match renumber(summary) {
+                Some(number) => SummaryEntry {
+                    is_this_session: summary.is_this_session,
+                    number,
+                    name: new_entries[(new_count - number) as usize].title.clone(),
+                },
+                None => summary.clone(),
+            };
+            if !summary_queue.contains(&fixed) {
+                summary_queue.push(fixed);
+            }
+        }
+
+        self.feed_state.entries = new_entries;
+        self.feed_state.queue = queue;
+        self.feed_state.summary_queue = summary_queue;
+        self.feed_state.latest_entry_number = self
+            .feed_state
+            .latest_entry_number
+            .saturating_sub(removed as u64);
+
+        removed
+    }
+
+    // Sum the sizes of files in the directory named after one of our entries.
+    pub fn disk_usage(&self) -> Result<u64, SubscriptionError> {
+        let names = self.entry_filenames();
+
+        Ok(self
+            .files_on_disk()?
+            .iter()
+            .filter(|f| names.contains(&f.name))
+            .map(|f| f.size)
+            .sum())
+    }
+
+    // Files in the directory that no entry would have been saved as.
+    pub fn untracked_files(&self) -> Result<Vec<PathBuf>, SubscriptionError> {
+        let names = self.entry_filenames();
+
+        let mut untracked = self
+            .files_on_disk()?
+            .into_iter()
+            .filter(|f| !names.contains(&f.name))
+            .map(|f| f.path)
+            .collect::<Vec<PathBuf>>();
+
+        untracked.sort();
+        Ok(untracked)
+    }
+
+    // Expected filenames of all entries, in NFC for comparing against disk.
+    fn entry_filenames(&self) -> Vec<String> {
+        self.feed_state
+            .entries
+            .iter()
+            .filter_map(|e| self.entry_filename(e))
+            .map(|n| n.nfc().collect::<String>())
+            .collect()
+    }
+
+    // Regular files in the directory, or none if it doesn't exist yet.
+    fn files_on_disk(&self) -> Result<Vec<DiskFile>, SubscriptionError> {
+        let dir = match fs::read_dir(&self.directory) {
+            Ok(dir) => dir,
+            Err(ref why) if why.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(why) => return Err(SubscriptionError::from(why)),
+        };
+
+        let mut files = Vec::new();
+        for file in dir {
+            let file = file?;
+            let metadata = file.metadata()?;
+            if metadata.is_file() {
+                files.push(DiskFile {
+                    // Some filesystems hand names back decomposed, so compare
+                    // in NFC.
+                    name: file.file_name().to_string_lossy().nfc().collect(),
+                    path: file.path(),
+                    size: metadata.len(),
+                });
+            }
+        }
+
+        Ok(files)
+    }
+
+    fn is_on_disk(&self, entry: &Entry, on_disk: &[DiskFile]) -> bool {
+        match self.entry_filename(entry) {
+            Some(name) => {
+                let name = name.nfc().collect::<String>();
+                on_disk.iter().any(|f| f.name == name)
+            }
+            None => false,
+        }
+    }
+
+    // Name puckfetcher gives the file downloaded from an entry's first url.
+    fn entry_filename(&self, entry: &Entry) -> Option<String> {
+        let basename = url_basename(entry.urls.first()?);
+        if basename.is_empty() {
+            return None;
+        }
+
+        if self.use_title_as_filename != Some(true) || entry.title.is_empty() {
+            return Some(basename.to_string());
+        }
+
+        let title = sanitize_filename(&entry.title);
+        match basename.rfind('.') {
+            Some(dot) => Some(format!("{}{}", title, &basename[dot..])),
+            None => Some(title),
+        }
+    }
+
+    // Enclosure lengths aren't cached, so they're given as 0 (unknown).
+    pub fn to_rss(&self) -> String {
+        let mut rss = String::new();
+        rss.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        rss.push_str("<rss version=\"2.0\">\n");
+        rss.push_str("  <channel>\n");
+        rss.push_str(&format!("    <title>{}</title>\n", escape_xml(&self.name)));
+        rss.push_str(&format!("    <link>{}</link>\n", escape_xml(&self.url)));
+        rss.push_str(&format!(
+            "    <description>{} backlog exported by podstats</description>\n",
+            escape_xml(&self.name)
+        ));
+
+        for entry in self.get_entries() {
+            let title = if entry.title.is_empty() {
+                format!("Episode {}", entry.number)
+            } else {
+                entry.title.clone()
+            };
+
+            rss.push_str("    <item>\n");
+            rss.push_str(&format!("      <title>{}</title>\n", escape_xml(&title)));
+            let guid = match entry.urls.first() {
+                Some(url) => {
+                    rss.push_str(&format!(
+                        "      <enclosure url=\"{}\" length=\"0\" type=\"{}\"/>\n",
+                        escape_xml(url),
+                        mime_type(url).unwrap_or("application/octet-stream")
+                    ));
+                    url.clone()
+                }
+                None => format!("{}#{}", self.original_url, entry.number),
+            };
+            rss.push_str(&format!(
+                "      <guid isPermaLink=\"false\">{}</guid>\n",
+                escape_xml(&guid)
+            ));
+            rss.push_str("    </item>\n");
+        }
+
+        rss.push_str("  </channel>\n");
+        rss.push_str("</rss>\n");
+
+        rss
+    }
+
+    // The cache doesn't keep MIME types, so they're guessed from each entry's
+    // first url. Entries that can't be guessed count as "unknown".
+    pub fn mime_breakdown(&self) -> HashMap<String, usize> {
+        let mut breakdown = HashMap::new();
+        for entry in &self.feed_state.entries {
+            let mime = entry
+                .urls
+                .first()
+                .and_then(|url| mime_type(url))
+                .unwrap_or("unknown");
+            *breakdown.entry(mime.to_string()).or_insert(0) += 1;
+        }
+
+        breakdown
+    }
+
+    pub fn session_stats(&self) -> SessionStats {
+        let this_session = self
+            .feed_state
+            .summary_queue
+            .iter()
+            .filter(|s| s.is_this_session)
+            .count();
+
+        SessionStats {
+            this_session,
+            prior_sessions: self.feed_state.summary_queue.len() - this_session,
+        }
+    }
+
+    // Extended M3U of the backlog in playback_order. Entries already on disk
+    // point at their file, the rest at their first url. Entries with no url
+    // have nothing to play and are left out. Durations aren't cached, so
+    // they're given as -1 (unknown).
+    pub fn to_m3u(&self) -> String {
+        let mut m3u = "#EXTM3U\n".to_string();
+        for view in self.playback_order() {
+            let url = match view.urls.first() {
+                Some(url) => url.clone(),
+                None => continue,
+            };
+
+            let entry = Entry {
+                title: view.title.clone(),
+                urls: view.urls.clone(),
+            };
+            let local = self
+                .entry_filename(&entry)
+                .map(|name| Path::new(&self.directory).join(name))
+                .filter(|path| path.is_file());
+            let location = match local {
+                Some(path) => path.to_string_lossy().to_string(),
+                None => url,
+            };
+
+            let title = if view.title.is_empty() {
+                format!("{} episode {}", self.name, view.number)
+            } else {
+                view.title.replace('\n', " ")
+            };
+            m3u.push_str(&format!("#EXTINF:-1,{}\n{}\n", title, location));
+        }
+
+        m3u
+    }
+
+    // Entries are stored newest first, so the oldest entry is number 1.
+    pub fn get_entries(&self) -> Vec<EntryView> {
+        let count = self.feed_state.entries.len() as u64;
+        self.feed_state
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(i, e)| EntryView::new(count - i as u64, e))
+            .collect::<Vec<EntryView>>()
+    }
+
+    // Queued entries, oldest first. The cache has no publish dates, so entry
+    // numbers decide the order; queued entries missing from the history go last.
+    pub fn playback_order(&self) -> Vec<EntryView> {
+        let entries = self.get_entries();
+        let mut queued = self
+            .feed_state
+            .queue
+            .iter()
+            .map(
+                |q| match self.feed_state.entries.iter().position(|e| e == q) {
+                    Some(i) => entries[i].clone(),
+                    None => EntryView::new(0, q),
+                },
+            )
+            .collect::<Vec<EntryView>>();
+        queued.sort_by_key(|e| (e.number == 0, e.number));
+
+        queued
+    }
+
+    // Entries whose titles match a regex. Use (?i) for case-insensitivity.
+    pub fn search(&self, pattern: &str) -> Result<Vec<EntryView>, SubscriptionError> {
+        let re = Regex::new(pattern)
+            .map_err(|why| SubscriptionError::InvalidPattern(why.to_string()))?;
+
+        Ok(self
+            .get_entries()
+            .into_iter()
+            .filter(|e| re.is_match(&e.title))
+            .collect::<Vec<EntryView>>())
+    }
+
+    // Point summary entries back at the entries they name, matching titles
+    // exactly, then ignoring case, spacing and punctuation. Each entry is
+    // claimed once, so a repeat listen of an entry is merged into the first.
+    // Summary entries that match nothing are dropped and returned as
+    // (number, name).
+    pub fn reconcile_summary(&mut self) -> Vec<(u64, String)> {
+        let entries = self.get_entries();
+        let loose = |s: &str| {
+            s.chars()
+                .filter(|c| c.is_alphanumeric())
+                .flat_map(char::to_lowercase)
+                .collect::<String>()
+        };
+
+        let mut orphaned = Vec::new();
+        let mut reconciled: Vec<SummaryEntry> = Vec::new();
+        for summary in &self.feed_state.summary_queue {
+            let name = loose(&summary.name);
+            let names = |e: &EntryView| {
+                e.title == summary.name || (!name.is_empty() && loose(&e.title) == name)
+            };
+            let open = entries
+                .iter()
+                .filter(|e| !reconciled.iter().any(|r| r.number == e.number))
+                .collect::<Vec<&EntryView>>();
+
+            let exact = open.iter().filter(|e| e.title == summary.name);
+            let found = match exact.clone().find(|e| e.number == summary.number) {
+                Some(e) => Some(e),
+                None => exact.clone().next(),
+            };
+            let found = found.or_else(|| open.iter().find(|e| names(e)));
+
+            match found {
+                Some(entry) => reconciled.push(SummaryEntry {
+                    is_this_session: summary.is_this_session,
+                    number: entry.number,
+                    name: entry.title.clone(),
+                }),
+                None if entries.iter().any(&names) => (),
+                None => orphaned.push((summary.number, summary.name.clone())),
+            }
+        }
+        self.feed_state.summary_queue = reconciled;
+
+        orphaned
+    }
+
+    // Stored entries missing from a fresh copy of the feed.
+    pub fn detect_dropped(&self, fresh: &[EntryView]) -> Vec<EntryView> {
+        self.get_entries()
+            .into_iter()
+            .filter(|e| !fresh.iter().any(|f| f.key() == e.key()))
+            .collect::<Vec<EntryView>>()
+    }
+
+    // Numbers of stored entries whose title or urls differ from the fresh
+    // entry with the same number.
+    pub fn changed_entries(&self, fresh: &[EntryView]) -> Vec<u64> {
+        let stored = self.get_entries();
+        fresh
+            .iter()
+            .filter(|f| stored.iter().any(|e| e.number == f.number && e != *f))
+            .map(|f| f.number)
+            .collect::<Vec<u64>>()
+    }
+
+    pub fn partition_by(&self, key: impl Fn(&EntryView) -> String) -> Vec<Subscription> {
+        let mut groups: Vec<(String, Vec<Entry>)> = Vec::new();
+        for (view, entry) in self.get_entries().iter().zip(&self.feed_state.entries) {
+            let k = key(view);
+            match groups.iter().position(|g| g.0 == k) {
+                Some(i) => groups[i].1.push(entry.clone()),
+                None => groups.push((k, vec![entry.clone()])),
+            }
+        }
+
+        let mut used: Vec<String> = Vec::new();
+        groups
+            .into_iter()
+            .map(|(k, entries)| {
+                let mut sub = self.clone();
+                sub.name = format!("{} ({})", self.name, k);
+                // Keys are caller data, so keep them to one component under
+                // our directory. Keys can sanitize alike, so number repeats.
+                let base = match sanitize_filename(&k).as_str() {
+                    "" | "." | ".." => "_".to_string(),
+                    d => d.to_string(),
+                };
+                let mut dir = base.clone();
+                let mut n = 2;
+                while used.contains(&dir) {
+                    dir = format!("{}_{}", base, n);
+                    n += 1;
+                }
+                used.push(dir.clone());
+                sub.directory = Path::new(&self.directory)
+                    .join(dir)
+                    .to_string_lossy()
+                    .to_string();
+
+                sub.feed_state.queue.retain(|e| entries.contains(e));
+
+                // Entry numbers restart within each partition.
+                let count = entries.len() as u64;
+                sub.feed_state.summary_queue = self
+                    .feed_state
+                    .summary_queue
+                    .iter()
+                    .filter_map(|s| {
+                        entries
+                            .iter()
+                            .position(|e| e.title == s.name)
+                            .map(|i| SummaryEntry {
+                                number: count - i as u64,
+                                ..s.clone()
+                            })
+                    })
+                    .collect();
+                sub.feed_state.latest_entry_number = count;
+                sub.feed_state.entries = entries;
+
+                sub
+            })
+            .collect::<Vec<Subscription>>()
+    }
+}
+
+// Feed state is private, so tests in other modules fill it in through these.
+#[cfg(test)]
+impl Subscription {
+    pub fn push_test_entry(&mut self, title: &str, url: &str, queued: bool) {
+        let entry = Entry {
+            title: title.to_string(),
+            urls: vec![url.to_string()],
+        };
+        if queued {
+            self.feed_state.queue.push(entry.clone());
+        }
+        self.feed_state.entries.insert(0, entry);
+        self.feed_state.latest_entry_number += 1;
+    }
+
+    pub fn push_test_summary(&mut self, number: u64, name: &str, is_this_session: bool) {
+        self.feed_state.summary_queue.push(SummaryEntry {
+            is_this_session,
+            number,
+            name: name.to_string(),
+        });
+    }
 }
 
 impl fmt::Display for Subscription {
@@ -64,6 +709,125 @@ impl fmt::Display for Subscription {
     }
 }
 
+#[derive(Debug)]
+pub enum SubscriptionError {
+    InvalidUrl(String),
+    Io(io::Error),
+    Decode(String),
+    Truncated,
+    UnsupportedVersion(u32),
+    InvalidPattern(String),
+    InvalidOpml(String),
+    RoundTrip(String),
+}
+
+impl fmt::Display for SubscriptionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SubscriptionError::InvalidUrl(ref url) => write!(f, "invalid feed url: {:?}", url),
+            SubscriptionError::Io(ref why) => write!(f, "io error: {}", why),
+            SubscriptionError::Decode(ref why) => write!(f, "couldn't decode cache: {}", why),
+            SubscriptionError::Truncated => {
+                write!(f, "cache is truncated, it may have been cut off mid-write")
+            }
+            SubscriptionError::UnsupportedVersion(v) => write!(f, "unsupported version: {}", v),
+            SubscriptionError::InvalidPattern(ref why) => write!(f, "invalid pattern: {}", why),
+            SubscriptionError::InvalidOpml(ref why) => write!(f, "invalid opml: {}", why),
+            SubscriptionError::RoundTrip(ref why) => write!(f, "round trip changed {}", why),
+        }
+    }
+}
+
+impl Error for SubscriptionError {}
+
+impl From<io::Error> for SubscriptionError {
+    fn from(why: io::Error) -> SubscriptionError {
+        SubscriptionError::Io(why)
+    }
+}
+
+// An http or https URL with a host, checked on construction.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FeedUrl(String);
+
+impl FeedUrl {
+    pub fn parse(url: &str) -> Result<FeedUrl, SubscriptionError> {
+        let url = url.trim();
+        match split_url(url) {
+            Some((ref scheme, ref host))
+                if (scheme == "http" || scheme == "https") && !host.is_empty() =>
+            {
+                Ok(FeedUrl(url.to_string()))
+            }
+            _ => Err(SubscriptionError::InvalidUrl(url.to_string())),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<FeedUrl> for String {
+    fn from(url: FeedUrl) -> String {
+        url.0
+    }
+}
+
+impl fmt::Display for FeedUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// Lowercase a URL's scheme and host and drop any trailing slash, leaving the
+// rest alone.
+fn normalize_url(url: &str) -> String {
+    let url = url.trim().trim_end_matches('/');
+    let sep = match url.find("://") {
+        Some(sep) => sep + 3,
+        None => return url.to_string(),
+    };
+
+    let end = match url[sep..].find(&['/', '?', '#'][..]) {
+        Some(end) => sep + end,
+        None => url.len(),
+    };
+
+    format!("{}{}", url[..end].to_lowercase(), &url[end..])
+}
+
+// Split a URL into its lowercased scheme and host, dropping any userinfo and port.
+fn split_url(url: &str) -> Option<(String, String)> {
+    if url.chars().any(char::is_whitespace) {
+        return None;
+    }
+
+    let sep = url.find("://")?;
+    let scheme = &url[..sep];
+    if scheme.is_empty() {
+        return None;
+    }
+
+    let rest = &url[sep + 3..];
+    let authority = match rest.find(&['/', '?', '#'][..]) {
+        Some(end) => &rest[..end],
+        None => rest,
+    };
+    let host = match authority.rfind('@') {
+        Some(at) => &authority[at + 1..],
+        None => authority,
+    };
+    let host = match host.rfind(':') {
+        Some(colon) if !host.ends_with(']') => &host[..colon],
+        _ => host,
+    };
+
+    Some((scheme.to_lowercase(), host.to_lowercase()))
+}
+
+// Everything stored is a struct or a Vec, so equal stores always encode to
+// identical bytes. Any map added to the state must be sorted before encoding.
 pub fn vec_serialize(subs: &Vec<Subscription>) -> Vec<u8> {
     let op_vec = rmps::to_vec(&subs);
 
@@ -104,7 +868,84 @@ pub fn vec_deserialize(sub_vec: &Vec<u8>) -> Option<Vec<Subscription>> {
     }
 }
 
+// Like vec_deserialize, but tells a cache cut off mid-write apart from one that
+// doesn't decode at all.
+pub fn try_vec_deserialize(sub_vec: &[u8]) -> Result<Vec<Subscription>, SubscriptionError> {
+    rmps::from_slice(sub_vec).map_err(decode_error)
+}
+
+fn decode_error(why: rmps::decode::Error) -> SubscriptionError {
+    match why {
+        rmps::decode::Error::InvalidMarkerRead(ref e)
+        | rmps::decode::Error::InvalidDataRead(ref e)
+            if e.kind() == io::ErrorKind::UnexpectedEof =>
+        {
+            SubscriptionError::Truncated
+        }
+        why => SubscriptionError::Decode(why.to_string()),
+    }
+}
+
+// Check a store survives encoding and decoding unchanged, naming the first
+// field that doesn't.
+pub fn assert_roundtrip(subs: &[Subscription]) -> Result<(), SubscriptionError> {
+    let bytes = rmps::to_vec(&subs).map_err(|why| SubscriptionError::RoundTrip(why.to_string()))?;
+    let decoded = try_vec_deserialize(&bytes)?;
+
+    if subs.len() != decoded.len() {
+        return Err(SubscriptionError::RoundTrip(format!(
+            "subscription count from {} to {}",
+            subs.len(),
+            decoded.len()
+        )));
+    }
+
+    for (i, (before, after)) in subs.iter().zip(&decoded).enumerate() {
+        if let Some(field) = first_difference(before, after) {
+            return Err(SubscriptionError::RoundTrip(format!(
+                "subscriptions[{}].{}",
+                i, field
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+fn first_difference(a: &Subscription, b: &Subscription) -> Option<&'static str> {
+    let fields = [
+        ("url", a.url == b.url),
+        ("original_url", a.original_url == b.original_url),
+        ("name", a.name == b.name),
+        ("directory", a.directory == b.directory),
+        ("backlog_limit", a.backlog_limit == b.backlog_limit),
+        (
+            "use_title_as_filename",
+            a.use_title_as_filename == b.use_title_as_filename,
+        ),
+        (
+            "feed_state.entries",
+            a.feed_state.entries == b.feed_state.entries,
+        ),
+        ("feed_state.queue", a.feed_state.queue == b.feed_state.queue),
+        (
+            "feed_state.latest_entry_number",
+            a.feed_state.latest_entry_number == b.feed_state.latest_entry_number,
+        ),
+        (
+            "feed_state.summary_queue",
+            a.feed_state.summary_queue == b.feed_state.summary_queue,
+        ),
+    ];
+
+    fields.iter().find(|f| !f.1).map(|f| f.0)
+}
+
 pub fn file_deserialize(path: &str) -> Option<Vec<Subscription>> {
+    if path.ends_with(".gz") {
+        return file_deserialize_gz(path);
+    }
+
     // Get path.
     let path = Path::new(&path);
     let display = path.display();
@@ -122,69 +963,823 @@ pub fn file_deserialize(path: &str) -> Option<Vec<Subscription>> {
         Err(why) => panic!("couldn't read {}: {}", display, why.description()),
     }
 
-    return vec_deserialize(&buffer);
-}
-
-fn process_directory(directory: Option<&str>) -> String {
-    return match directory {
-        // TODO expand given dir.
-        Some(x) => x.to_string(),
-        // TODO properly default str.
-        None => "fakedir".to_string(),
+    match try_vec_deserialize(&buffer) {
+        Ok(subs) => Some(subs),
+        Err(why) => panic!("couldn't decode {}: {}", display, why),
     }
 }
 
-#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
-struct FeedState {
-    entries: Vec<Entry>,
-    // entries_state_dict
-    queue: Vec<Entry>,
-    latest_entry_number: u64,
-    summary_queue: Vec<SummaryEntry>,
-    // last_modified
-    // etag
-}
+pub fn file_serialize_gz(path: &str, subs: &Vec<Subscription>) {
+    let path = Path::new(&path);
+    let display = path.display();
 
-#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
-struct Entry {
-    title: String,
-    urls: Vec<String>,
-}
+    let file = match File::create(path) {
+        Ok(file) => file,
+        Err(why) => panic!("couldn't create {}: {}", display, why),
+    };
 
-#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
-struct SummaryEntry {
-    is_this_session: bool,
-    number: u64,
-    name: String,
+    let mut encoder = GzEncoder::new(file, Compression::default());
+    match encoder.write_all(vec_serialize(subs).as_slice()) {
+        Ok(_) => (),
+        Err(why) => panic!("couldn't write to {}: {}", display, why),
+    };
+
+    match encoder.finish() {
+        Ok(_) => (),
+        Err(why) => panic!("couldn't finish writing {}: {}", display, why),
+    };
 }
 
-impl fmt::Display for FeedState {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:#?}", self)
+pub fn file_deserialize_gz(path: &str) -> Option<Vec<Subscription>> {
+    let path = Path::new(&path);
+    let display = path.display();
+
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(why) => panic!("couldn't open {}: {}", display, why),
+    };
+
+    // Decompress file contents into buffer.
+    let mut buffer = Vec::new();
+    match GzDecoder::new(file).read_to_end(&mut buffer) {
+        Ok(_) => (),
+        Err(why) => panic!("couldn't decompress {}: {}", display, why),
+    }
+
+    match try_vec_deserialize(&buffer) {
+        Ok(subs) => Some(subs),
+        Err(why) => panic!("couldn't decode {}: {}", display, why),
     }
 }
 
-#[test]
-fn serialize_deserialize_test() {
-    let sub = Subscription::new("testurl", "testname", None);
-    let s = serialize(&sub);
-    let re_sub = deserialize(&s);
+// Cache path from the environment, falling back to puckfetcher's XDG cache.
+pub fn default_cache_location() -> Option<String> {
+    if let Some(path) = env::var_os(STATE_ENV_VAR) {
+        return Some(path.to_string_lossy().to_string());
+    }
 
-    assert_eq!(sub, re_sub.unwrap());
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("puckfetcher").ok()?;
+    xdg_dirs
+        .find_cache_file("puckcache")
+        .map(|p| p.to_string_lossy().to_string())
 }
 
-#[test]
-fn vec_serialize_deserialize_test() {
-    let sub = Subscription::new("testurl", "testname", None);
-    let mut subs = Vec::new();
-    subs.push(sub);
+// A missing cache is treated as an empty store. Nothing is written to disk,
+// since the cache belongs to puckfetcher.
+pub fn load_default() -> Result<Vec<Subscription>, SubscriptionError> {
+    let location = match default_cache_location() {
+        Some(location) => location,
+        None => return Ok(Vec::new()),
+    };
 
-    let s = vec_serialize(&subs);
+    let path = Path::new(&location);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut buffer = Vec::new();
+    let mut file = File::open(path)?;
+    if location.ends_with(".gz") {
+        GzDecoder::new(file).read_to_end(&mut buffer)?;
+    } else {
+        file.read_to_end(&mut buffer)?;
+    }
+
+    try_vec_deserialize(&buffer)
+}
+
+// Unlistened queued entries across subscriptions, taken one per show per
+// round so no show dominates. Each round visits shows with the most remaining
+// backlog first, and each show gives up its oldest entry.
+pub fn smart_queue(subs: &[Subscription], max: usize) -> Vec<(usize, EntryView)> {
+    let mut pending = subs
+        .iter()
+        .map(|s| {
+            s.playback_order()
+                .into_iter()
+                .filter(|e| !s.is_listened(e))
+                .collect::<Vec<EntryView>>()
+                .into_iter()
+        })
+        .collect::<Vec<_>>();
+
+    let mut queue = Vec::new();
+    while queue.len() < max && pending.iter().any(|p| p.len() > 0) {
+        let mut round = (0..subs.len())
+            .filter(|&i| pending[i].len() > 0)
+            .collect::<Vec<usize>>();
+        round.sort_by_key(|&i| (Reverse(pending[i].len()), i));
+
+        for i in round.into_iter().take(max - queue.len()) {
+            if let Some(entry) = pending[i].next() {
+                queue.push((i, entry));
+            }
+        }
+    }
+
+    queue
+}
+
+// Table of entry and queue counts per subscription, with a totals row. The
+// cache has no last_fetched time, so there's no fetch age column.
+pub fn summary_report(subs: &[Subscription]) -> String {
+    let total_entries: u64 = subs.iter().map(|s| s.get_latest_entry_number()).sum();
+    let total_queued: usize = subs.iter().map(|s| s.feed_state.queue.len()).sum();
+
+    let name_width = subs
+        .iter()
+        .map(|s| s.name.chars().count())
+        .chain(vec!["Name".len(), "Total".len()])
+        .max()
+        .unwrap_or(0);
+    let entries_width = "Entries".len().max(total_entries.to_string().len());
+    let queued_width = "Queued".len().max(total_queued.to_string().len());
+
+    let row = |name: &str, entries: &str, queued: &str| {
+        let padding = name_width - name.chars().count();
+        format!(
+            "{}{}  {:>ew$}  {:>qw$}\n",
+            name,
+            " ".repeat(padding),
+            entries,
+            queued,
+            ew = entries_width,
+            qw = queued_width
+        )
+    };
+
+    let mut report = row("Name", "Entries", "Queued");
+    for sub in subs {
+        report.push_str(&row(
+            &sub.name,
+            &sub.get_latest_entry_number().to_string(),
+            &sub.feed_state.queue.len().to_string(),
+        ));
+    }
+    report.push_str(&row(
+        "Total",
+        &total_entries.to_string(),
+        &total_queued.to_string(),
+    ));
+
+    report
+}
+
+// Indices of subscriptions left on the placeholder directory, or none at all.
+pub fn misconfigured_directories(subs: &[Subscription]) -> Vec<usize> {
+    subs.iter()
+        .enumerate()
+        .filter(|&(_, s)| has_placeholder_directory(s))
+        .map(|(i, _)| i)
+        .collect::<Vec<usize>>()
+}
+
+fn has_placeholder_directory(sub: &Subscription) -> bool {
+    sub.directory.trim().is_empty() || sub.directory == DEFAULT_DIRECTORY
+}
+
+// Fix entry counts, dedupe entries and reconcile summaries in every
+// subscription, and flag placeholder directories, which need a human to fix.
+pub fn repair(subs: &mut [Subscription]) -> RepairReport {
+    let mut report = RepairReport::default();
+    for (i, sub) in subs.iter_mut().enumerate() {
+        // Everything else assumes the stored count matches the history.
+        let count = sub.feed_state.entries.len() as u64;
+        if sub.feed_state.latest_entry_number != count {
+            report
+                .entry_counts_reset
+                .push((i, sub.feed_state.latest_entry_number, count));
+            sub.feed_state.latest_entry_number = count;
+        }
+
+        let removed = sub.dedupe_entries();
+        if removed > 0 {
+            report.duplicates_removed.push((i, removed));
+        }
+
+        let before = sub.feed_state.summary_queue.clone();
+        let orphaned = sub.reconcile_summary();
+        let after = &sub.feed_state.summary_queue;
+        let relinked = before
+            .iter()
+            .filter(|s| !orphaned.contains(&(s.number, s.name.clone())))
+            .filter(|s| !after.contains(s))
+            .count();
+        if relinked > 0 {
+            report.summaries_relinked.push((i, relinked));
+        }
+
+        for (number, name) in orphaned {
+            report.summaries_orphaned.push((i, number, name));
+        }
+    }
+    report.misconfigured_directories = misconfigured_directories(subs);
+
+    report
+}
+
+// Names shared by more than one subscription, with the indices sharing each.
+pub fn name_collisions(subs: &[Subscription]) -> Vec<(String, Vec<usize>)> {
+    let mut groups: Vec<(String, Vec<usize>)> = Vec::new();
+    for (i, sub) in subs.iter().enumerate() {
+        match groups.iter().position(|g| g.0 == sub.name) {
+            Some(g) => groups[g].1.push(i),
+            None => groups.push((sub.name.clone(), vec![i])),
+        }
+    }
+
+    groups
+        .into_iter()
+        .filter(|g| g.1.len() > 1)
+        .collect::<Vec<(String, Vec<usize>)>>()
+}
+
+// Enclosure urls carried by more than one subscription, with the indices of
+// the subscriptions carrying each. Repeats within one feed are left to
+// dedupe_entries.
+pub fn cross_feed_duplicates(subs: &[Subscription]) -> Vec<(String, Vec<usize>)> {
+    let mut groups: Vec<(String, Vec<usize>)> = Vec::new();
+    for (i, sub) in subs.iter().enumerate() {
+        for url in sub.feed_state.entries.iter().flat_map(|e| e.urls.iter()) {
+            match groups.iter().position(|g| &g.0 == url) {
+                Some(g) if groups[g].1.contains(&i) => {}
+                Some(g) => groups[g].1.push(i),
+                None => groups.push((url.clone(), vec![i])),
+            }
+        }
+    }
+
+    groups
+        .into_iter()
+        .filter(|g| g.1.len() > 1)
+        .collect::<Vec<(String, Vec<usize>)>>()
+}
+
+// Trim the combined queue to the newest limit entries, dropping the oldest
+// wherever they are. The cache has no publish dates, so an entry's age is how
+// far it sits behind its feed's newest entry; queued entries missing from the
+// history count as oldest. Ties go to earlier subscriptions.
+pub fn apply_global_backlog_limit(subs: &mut [Subscription], limit: usize) {
+    let mut queued = Vec::new();
+    for (i, sub) in subs.iter().enumerate() {
+        let entries = &sub.feed_state.entries;
+        for (q, entry) in sub.feed_state.queue.iter().enumerate() {
+            let age = entries
+                .iter()
+                .position(|e| e == entry)
+                .unwrap_or(entries.len());
+            queued.push((age, i, q));
+        }
+    }
+    queued.sort();
+
+    let mut keep = vec![Vec::new(); subs.len()];
+    for &(_, i, q) in queued.iter().take(limit) {
+        keep[i].push(q);
+    }
+
+    for (sub, keep) in subs.iter_mut().zip(keep) {
+        let queue = sub
+            .feed_state
+            .queue
+            .iter()
+            .enumerate()
+            .filter(|&(q, _)| keep.contains(&q))
+            .map(|(_, e)| e.clone())
+            .collect::<Vec<Entry>>();
+        sub.feed_state.queue = queue;
+    }
+}
+
+// Shrink long-lived state by dropping listened history. This is lossy: the
+// summary is cleared and entries that aren't queued lose their titles. Their
+// urls stay, so dedupe_entries can still spot them coming back.
+pub fn compact(subs: &mut [Subscription]) {
+    for sub in subs.iter_mut() {
+        let state = &mut sub.feed_state;
+        state.summary_queue.clear();
+        for entry in state.entries.iter_mut() {
+            if !state.queue.contains(entry) {
+                entry.title.clear();
+            }
+        }
+    }
+}
+
+// Move directories under old_base to the same place under new_base. Matching
+// is by whole path components, so "/pods" doesn't claim "/podcasts".
+pub fn rebase_directories(subs: &mut [Subscription], old_base: &str, new_base: &str) -> usize {
+    // Every path starts with the empty path, so it would claim them all.
+    if old_base.is_empty() {
+        return 0;
+    }
+
+    let mut changed = 0;
+    for sub in subs.iter_mut() {
+        let rebased = match Path::new(&sub.directory).strip_prefix(old_base) {
+            Ok(rest) if rest.as_os_str().is_empty() => Path::new(new_base).to_path_buf(),
+            Ok(rest) => Path::new(new_base).join(rest),
+            Err(_) => continue,
+        };
+
+        let rebased = rebased.to_string_lossy().to_string();
+        if rebased != sub.directory {
+            sub.directory = rebased;
+            changed += 1;
+        }
+    }
+
+    changed
+}
+
+// Indices of subscriptions whose url has moved to another scheme or host.
+pub fn scheme_mismatches(subs: &[Subscription]) -> Vec<usize> {
+    subs.iter()
+        .enumerate()
+        .filter(
+            |&(_, s)| match (split_url(&s.url), split_url(&s.original_url)) {
+                (Some(current), Some(original)) => current != original,
+                _ => false,
+            },
+        )
+        .map(|(i, _)| i)
+        .collect::<Vec<usize>>()
+}
+
+pub fn to_opml(subs: &[Subscription]) -> String {
+    let mut opml = opml_header();
+    for sub in subs {
+        opml.push_str(&opml_outline(sub, 2));
+    }
+    opml.push_str(&opml_footer());
+
+    opml
+}
+
+pub fn to_opml_grouped(subs: &[Subscription]) -> String {
+    // Keep groups in the order their directories first appear.
+    let mut groups: Vec<(&str, Vec<&Subscription>)> = Vec::new();
+    let mut ungrouped = Vec::new();
+    for sub in subs {
+        if has_placeholder_directory(sub) {
+            ungrouped.push(sub);
+            continue;
+        }
+
+        match groups.iter().position(|g| g.0 == sub.directory) {
+            Some(i) => groups[i].1.push(sub),
+            None => groups.push((&sub.directory, vec![sub])),
+        }
+    }
+
+    let mut opml = opml_header();
+    for sub in ungrouped {
+        opml.push_str(&opml_outline(sub, 2));
+    }
+
+    for (directory, members) in groups {
+        let dir = escape_xml(directory);
+        opml.push_str(&format!(
+            "    <outline text=\"{}\" title=\"{}\">\n",
+            dir, dir
+        ));
+        for sub in members {
+            opml.push_str(&opml_outline(sub, 3));
+        }
+        opml.push_str("    </outline>\n");
+    }
+    opml.push_str(&opml_footer());
+
+    opml
+}
+
+// Feeds are matched by url first, then by name. A matched pair whose name or
+// url differs is a mismatch.
+pub fn opml_drift(subs: &[Subscription], opml: &str) -> Result<OpmlDrift, SubscriptionError> {
+    let doc = roxmltree::Document::parse(opml)
+        .map_err(|why| SubscriptionError::InvalidOpml(why.to_string()))?;
+
+    let feeds = doc
+        .descendants()
+        .filter(|n| n.has_tag_name("outline"))
+        .filter_map(|n| {
+            let url = n.attribute("xmlUrl")?;
+            let name = n.attribute("text").or_else(|| n.attribute("title"));
+            Some(OpmlFeed {
+                name: name.unwrap_or("").to_string(),
+                url: url.to_string(),
+            })
+        })
+        .collect::<Vec<OpmlFeed>>();
+
+    let mut drift = OpmlDrift::default();
+    let mut matched = vec![false; subs.len()];
+    for feed in feeds {
+        let same_url = |s: &Subscription| s.url == feed.url || s.original_url == feed.url;
+        let by_url = (0..subs.len()).find(|&i| !matched[i] && same_url(&subs[i]));
+        let found =
+            by_url.or_else(|| (0..subs.len()).find(|&i| !matched[i] && subs[i].name == feed.name));
+
+        match found {
+            Some(i) => {
+                matched[i] = true;
+                if subs[i].name != feed.name || !same_url(&subs[i]) {
+                    drift.mismatched.push((i, feed));
+                }
+            }
+            None => drift.only_in_opml.push(feed),
+        }
+    }
+    drift.only_in_state = (0..subs.len()).filter(|&i| !matched[i]).collect();
+
+    Ok(drift)
+}
+
+// What a remote store has that the local one lacks. Subscriptions match by
+// id() and entries by first url, then title. Entries are given as the remote
+// has them, with the index of the local subscription they belong to.
+pub fn sync_plan(local: &[Subscription], remote: &[Subscription]) -> SyncPlan {
+    let mut plan = SyncPlan::default();
+    for theirs in remote {
+        let i = match local.iter().position(|l| l.id() == theirs.id()) {
+            Some(i) => i,
+            None => {
+                plan.new_subscriptions.push(theirs.clone());
+                continue;
+            }
+        };
+
+        let ours = local[i].get_entries();
+        for entry in theirs.get_entries().into_iter().rev() {
+            if !ours.iter().any(|e| e.key() == entry.key()) {
+                plan.new_entries.push((i, entry));
+            }
+        }
+
+        let listened = local[i].listened_entries();
+        for entry in theirs.listened_entries() {
+            if !listened.iter().any(|e| e.key() == entry.key()) {
+                plan.newly_listened.push((i, entry));
+            }
+        }
+    }
+
+    plan
+}
+
+// New entries go in as the newest, oldest of them first, so the history stays
+// newest first. Listened marks go in last, so they can name merged entries.
+pub fn apply_sync_plan(local: &mut Vec<Subscription>, plan: &SyncPlan) {
+    for &(i, ref view) in &plan.new_entries {
+        let state = &mut local[i].feed_state;
+        state.entries.insert(
+            0,
+            Entry {
+                title: view.title.clone(),
+                urls: view.urls.clone(),
+            },
+        );
+        state.latest_entry_number += 1;
+    }
+
+    for &(i, ref view) in &plan.newly_listened {
+        let sub = &mut local[i];
+        let found = sub
+            .get_entries()
+            .into_iter()
+            .find(|e| e.key() == view.key());
+        if let Some(entry) = found {
+            sub.feed_state.summary_queue.push(SummaryEntry {
+                is_this_session: false,
+                number: entry.number,
+                name: entry.title,
+            });
+        }
+    }
+
+    local.extend(plan.new_subscriptions.iter().cloned());
+}
+
+fn opml_header() -> String {
+    let mut header = String::new();
+    header.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    header.push_str("<opml version=\"2.0\">\n");
+    header.push_str("  <head>\n");
+    header.push_str("    <title>podstats subscriptions</title>\n");
+    header.push_str("  </head>\n");
+    header.push_str("  <body>\n");
+
+    header
+}
+
+fn opml_footer() -> String {
+    "  </body>\n</opml>\n".to_string()
+}
+
+fn opml_outline(sub: &Subscription, depth: usize) -> String {
+    let name = escape_xml(&sub.name);
+    format!(
+        "{}<outline type=\"rss\" text=\"{}\" title=\"{}\" xmlUrl=\"{}\"/>\n",
+        "  ".repeat(depth),
+        name,
+        name,
+        escape_xml(&sub.url)
+    )
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// Last path segment of a url, without any query or fragment.
+fn url_basename(url: &str) -> &str {
+    let path = match url.find(&['?', '#'][..]) {
+        Some(end) => &url[..end],
+        None => url,
+    };
+
+    path.rsplit('/').next().unwrap_or("")
+}
+
+// Guess an enclosure's MIME type from its file extension.
+fn mime_type(url: &str) -> Option<&'static str> {
+    let basename = url_basename(url);
+    let extension = match basename.rfind('.') {
+        Some(dot) => basename[dot + 1..].to_lowercase(),
+        None => String::new(),
+    };
+
+    match extension.as_str() {
+        "mp3" => Some("audio/mpeg"),
+        "m4a" => Some("audio/mp4"),
+        "aac" => Some("audio/aac"),
+        "ogg" | "oga" => Some("audio/ogg"),
+        "opus" => Some("audio/opus"),
+        "wav" => Some("audio/wav"),
+        "mp4" | "m4v" => Some("video/mp4"),
+        "mov" => Some("video/quicktime"),
+        _ => None,
+    }
+}
+
+// Normalized to NFC so canonically equal titles give the same bytes.
+fn sanitize_filename(name: &str) -> String {
+    name.trim()
+        .nfc()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+fn process_directory(directory: Option<&str>) -> String {
+    return match directory {
+        // TODO expand given dir.
+        Some(x) => x.to_string(),
+        // TODO properly default str.
+        None => DEFAULT_DIRECTORY.to_string(),
+    }
+}
+
+// A file found in a subscription's directory, named in NFC.
+struct DiskFile {
+    name: String,
+    path: PathBuf,
+    size: u64,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+struct FeedState {
+    entries: Vec<Entry>,
+    // entries_state_dict
+    queue: Vec<Entry>,
+    latest_entry_number: u64,
+    summary_queue: Vec<SummaryEntry>,
+    // last_modified
+    // etag
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+struct Entry {
+    title: String,
+    urls: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+struct SummaryEntry {
+    is_this_session: bool,
+    number: u64,
+    name: String,
+}
+
+// Listened entries split at the session boundary. The boundary is wherever
+// puckfetcher last reset is_this_session: entries still carrying the flag
+// belong to the current session, the rest to earlier ones.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SessionStats {
+    pub this_session: usize,
+    pub prior_sessions: usize,
+}
+
+impl fmt::Display for SessionStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} this session, {} in earlier sessions",
+            self.this_session, self.prior_sessions
+        )
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OpmlFeed {
+    pub name: String,
+    pub url: String,
+}
+
+// Differences between the cache and an OPML feed list. Subscriptions are
+// given by index.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct OpmlDrift {
+    pub only_in_state: Vec<usize>,
+    pub only_in_opml: Vec<OpmlFeed>,
+    pub mismatched: Vec<(usize, OpmlFeed)>,
+}
+
+// What repair fixed and flagged. Subscriptions are given by index, entry
+// counts as (index, old, new) and orphaned summaries as (index, number, name).
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RepairReport {
+    pub entry_counts_reset: Vec<(usize, u64, u64)>,
+    pub duplicates_removed: Vec<(usize, usize)>,
+    pub summaries_relinked: Vec<(usize, usize)>,
+    pub summaries_orphaned: Vec<(usize, u64, String)>,
+    pub misconfigured_directories: Vec<usize>,
+}
+
+// Changes sync_plan found, for apply_sync_plan to make. Entries carry the
+// index of the local subscription they go into.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SyncPlan {
+    pub new_subscriptions: Vec<Subscription>,
+    pub new_entries: Vec<(usize, EntryView)>,
+    pub newly_listened: Vec<(usize, EntryView)>,
+}
+
+// Read-only copy of an entry, numbered from the oldest entry.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EntryView {
+    pub number: u64,
+    pub title: String,
+    pub urls: Vec<String>,
+}
+
+impl EntryView {
+    fn new(number: u64, entry: &Entry) -> EntryView {
+        EntryView {
+            number,
+            title: entry.title.clone(),
+            urls: entry.urls.clone(),
+        }
+    }
+
+    // Entries have no guid, so they're identified by first url, then title.
+    fn key(&self) -> &str {
+        match self.urls.first() {
+            Some(url) => url,
+            None => &self.title,
+        }
+    }
+}
+
+impl fmt::Display for FeedState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#?}", self)
+    }
+}
+
+#[test]
+fn serialize_deserialize_test() {
+    let sub = Subscription::new("testurl", "testname", None);
+    let s = serialize(&sub);
+    let re_sub = deserialize(&s);
+
+    assert_eq!(sub, re_sub.unwrap());
+}
+
+#[test]
+fn vec_serialize_deserialize_test() {
+    let sub = Subscription::new("testurl", "testname", None);
+    let mut subs = Vec::new();
+    subs.push(sub);
+
+    let s = vec_serialize(&subs);
     let re_subs = vec_deserialize(&s);
 
     assert_eq!(subs, re_subs.unwrap());
 }
 
+#[test]
+fn assert_roundtrip_test() {
+    let mut sub = Subscription::new("testurl", "testname", Some("testdir"));
+    sub.backlog_limit = None;
+    sub.feed_state.entries.push(Entry {
+        title: "testentry \u{e9}".to_string(),
+        urls: vec!["testentryurl".to_string(), "testentrymirror".to_string()],
+    });
+    sub.feed_state.queue = sub.feed_state.entries.clone();
+    sub.feed_state.latest_entry_number = 1;
+    sub.feed_state.summary_queue.push(SummaryEntry {
+        is_this_session: true,
+        number: 1,
+        name: "testentry \u{e9}".to_string(),
+    });
+    let subs = vec![
+        sub.clone(),
+        Subscription::new("testurl2", "testname2", None),
+    ];
+
+    assert!(assert_roundtrip(&subs).is_ok());
+    assert!(assert_roundtrip(&[]).is_ok());
+
+    let mut changed = sub.clone();
+    changed.feed_state.queue.clear();
+    assert_eq!(None, first_difference(&sub, &sub.clone()));
+    assert_eq!(Some("feed_state.queue"), first_difference(&sub, &changed));
+}
+
+#[test]
+fn bundle_test() {
+    let mut sub = Subscription::new("testurl", "testname", Some("testdir"));
+    sub.feed_state.entries.push(Entry {
+        title: "testentry".to_string(),
+        urls: vec!["testentryurl".to_string()],
+    });
+    sub.feed_state.latest_entry_number = 1;
+
+    let bundle = sub.to_bundle();
+    assert_eq!(sub, Subscription::from_bundle(&bundle).unwrap());
+
+    let future = rmps::to_vec(&(BUNDLE_VERSION + 1, "some newer layout")).unwrap();
+    match Subscription::from_bundle(&future) {
+        Err(SubscriptionError::UnsupportedVersion(v)) => assert_eq!(BUNDLE_VERSION + 1, v),
+        res => panic!("expected unsupported version, got {:?}", res),
+    }
+
+    match Subscription::from_bundle(&bundle[..bundle.len() - 1]) {
+        Err(SubscriptionError::Truncated) => (),
+        res => panic!("expected truncation, got {:?}", res),
+    }
+}
+
+#[test]
+fn try_vec_deserialize_test() {
+    let mut sub = Subscription::new("testurl", "testname", None);
+    sub.feed_state.entries.push(Entry {
+        title: "testentry".to_string(),
+        urls: vec!["testentryurl".to_string()],
+    });
+    let subs = vec![sub];
+
+    let s = vec_serialize(&subs);
+    assert_eq!(subs, try_vec_deserialize(&s).unwrap());
+
+    for len in 0..s.len() {
+        match try_vec_deserialize(&s[..len]) {
+            Err(SubscriptionError::Truncated) => (),
+            res => panic!("expected truncation at {} bytes, got {:?}", len, res),
+        }
+    }
+
+    // A string where a list of subscriptions belongs is corrupt, not short.
+    match try_vec_deserialize(&[0xa1, b'x']) {
+        Err(SubscriptionError::Decode(_)) => (),
+        res => panic!("expected decode error, got {:?}", res),
+    }
+}
+
+#[test]
+fn vec_serialize_stable_test() {
+    let mut sub = Subscription::new("testurl", "testname", None);
+    sub.feed_state.entries.push(Entry {
+        title: "testentry".to_string(),
+        urls: vec!["testentryurl".to_string()],
+    });
+    let subs = vec![sub];
+
+    // Equal content, built separately, must encode to the same bytes.
+    let copy = vec_deserialize(&vec_serialize(&subs)).unwrap();
+
+    assert_eq!(vec_serialize(&subs), vec_serialize(&subs));
+    assert_eq!(vec_serialize(&subs), vec_serialize(&copy));
+}
+
 #[test]
 fn file_serialize_test() {
     let test_path = "tmp_test.txt";
@@ -220,3 +1815,1262 @@ fn file_serialize_test() {
         Err(why) => panic!("couldn't remove file: {}", why.description()),
     };
 }
+
+#[test]
+fn smart_queue_test() {
+    let mut big = Subscription::new("testurl1", "testname1", None);
+    for i in (1..5).rev() {
+        big.feed_state.entries.push(Entry {
+            title: format!("big{}", i),
+            urls: Vec::new(),
+        });
+    }
+    big.feed_state.queue = big.feed_state.entries.clone();
+    big.feed_state.summary_queue.push(SummaryEntry {
+        is_this_session: true,
+        number: 1,
+        name: "big1".to_string(),
+    });
+
+    let mut small = Subscription::new("testurl2", "testname2", None);
+    small.feed_state.entries.push(Entry {
+        title: "small1".to_string(),
+        urls: Vec::new(),
+    });
+    small.feed_state.queue = small.feed_state.entries.clone();
+
+    let subs = vec![small, big];
+
+    let picks = |max| {
+        smart_queue(&subs, max)
+            .into_iter()
+            .map(|(i, e)| (i, e.title))
+            .collect::<Vec<(usize, String)>>()
+    };
+
+    let expected = vec![
+        (1, "big2".to_string()),
+        (0, "small1".to_string()),
+        (1, "big3".to_string()),
+        (1, "big4".to_string()),
+    ];
+    assert_eq!(expected, picks(10));
+    assert_eq!(expected[..3].to_vec(), picks(3));
+    assert!(picks(0).is_empty());
+}
+
+#[test]
+fn summary_report_test() {
+    let mut long = Subscription::new("testurl1", "a longer testname", None);
+    long.feed_state.latest_entry_number = 12;
+    long.feed_state.queue.push(Entry::default());
+    let mut short = Subscription::new("testurl2", "short", None);
+    short.feed_state.latest_entry_number = 3;
+    short.feed_state.queue.push(Entry::default());
+    short.feed_state.queue.push(Entry::default());
+
+    let subs = vec![long, short];
+
+    let report = summary_report(&subs);
+
+    let expected = vec![
+        "Name               Entries  Queued",
+        "a longer testname       12       1",
+        "short                    3       2",
+        "Total                   15       3",
+    ];
+    assert_eq!(expected, report.lines().collect::<Vec<&str>>());
+}
+
+#[test]
+fn misconfigured_directories_test() {
+    let subs = vec![
+        Subscription::new("testurl1", "testname1", Some("testdir")),
+        Subscription::new("testurl2", "testname2", None),
+        Subscription::new("testurl3", "testname3", Some("")),
+    ];
+
+    assert_eq!(vec![1, 2], misconfigured_directories(&subs));
+}
+
+#[test]
+fn repair_test() {
+    let mut messy = Subscription::new("testurl1", "testname1", Some("testdir"));
+    messy.feed_state.entries = vec![
+        Entry {
+            title: "testentry2".to_string(),
+            urls: vec!["testentryurl2".to_string()],
+        },
+        Entry {
+            title: "testentry1".to_string(),
+            urls: vec!["testentryurl1".to_string()],
+        },
+        Entry {
+            title: "testentry1".to_string(),
+            urls: vec!["testentryurl1".to_string()],
+        },
+    ];
+    messy.feed_state.latest_entry_number = 3;
+    for &(number, name) in &[(2, "testentry2"), (9, "testentry1"), (4, "gone")] {
+        messy.feed_state.summary_queue.push(SummaryEntry {
+            is_this_session: false,
+            number,
+            name: name.to_string(),
+        });
+    }
+
+    let clean = Subscription::new("testurl2", "testname2", Some("testdir2"));
+    let mut placeholder = Subscription::new("testurl3", "testname3", None);
+    placeholder.feed_state.latest_entry_number = 5;
+
+    let mut subs = vec![messy, clean.clone(), placeholder];
+    let report = repair(&mut subs);
+
+    assert_eq!(vec![(2, 5, 0)], report.entry_counts_reset);
+    assert_eq!(0, subs[2].get_latest_entry_number());
+
+    assert_eq!(vec![(0, 1)], report.duplicates_removed);
+    assert_eq!(vec![(0, 1)], report.summaries_relinked);
+    assert_eq!(vec![(0, 4, "gone".to_string())], report.summaries_orphaned);
+    assert_eq!(vec![2], report.misconfigured_directories);
+    assert_eq!(clean, subs[1]);
+
+    // Both listened entries are still marked, under their real numbers.
+    let summaries = subs[0]
+        .feed_state
+        .summary_queue
+        .iter()
+        .map(|s| (s.number, s.name.as_str()))
+        .collect::<Vec<(u64, &str)>>();
+    assert_eq!(vec![(2, "testentry2"), (1, "testentry1")], summaries);
+
+    // A second pass finds nothing left to fix.
+    let report = repair(&mut subs);
+    assert_eq!(
+        RepairReport {
+            misconfigured_directories: vec![2],
+            ..RepairReport::default()
+        },
+        report
+    );
+}
+
+#[test]
+fn name_collisions_test() {
+    let subs = vec![
+        Subscription::new("testurl1", "testname", None),
+        Subscription::new("testurl2", "othername", None),
+        Subscription::new("testurl3", "testname", None),
+    ];
+
+    assert_eq!(
+        vec![("testname".to_string(), vec![0, 2])],
+        name_collisions(&subs)
+    );
+    assert!(name_collisions(&subs[..2]).is_empty());
+}
+
+#[test]
+fn cross_feed_duplicates_test() {
+    let shared = "https://example.com/shared.mp3".to_string();
+
+    let mut show = Subscription::new("testurl1", "testshow", None);
+    show.feed_state.entries.push(Entry {
+        title: "testentry1".to_string(),
+        urls: vec![shared.clone()],
+    });
+    show.feed_state.entries.push(Entry {
+        title: "testentry2".to_string(),
+        urls: vec!["https://example.com/only-show.mp3".to_string()],
+    });
+
+    let mut network = Subscription::new("testurl2", "testnetwork", None);
+    network.feed_state.entries.push(Entry {
+        title: "testentry1".to_string(),
+        urls: vec![shared.clone()],
+    });
+    network.feed_state.entries.push(Entry {
+        title: "testentry1 again".to_string(),
+        urls: vec![shared.clone()],
+    });
+
+    let subs = vec![show, network];
+    assert_eq!(vec![(shared, vec![0, 1])], cross_feed_duplicates(&subs));
+    assert!(cross_feed_duplicates(&subs[1..]).is_empty());
+}
+
+#[test]
+fn apply_global_backlog_limit_test() {
+    let mut subs = Vec::new();
+    for (name, count) in &[("testname1", 3), ("testname2", 2), ("testname3", 1)] {
+        let mut sub = Subscription::new("testurl", name, None);
+        for i in 1..count + 1 {
+            let entry = Entry {
+                title: format!("{}-{}", name, i),
+                urls: Vec::new(),
+            };
+            sub.feed_state.entries.insert(0, entry.clone());
+            sub.feed_state.queue.push(entry);
+        }
+        subs.push(sub);
+    }
+
+    apply_global_backlog_limit(&mut subs, 4);
+
+    let queued = subs
+        .iter()
+        .map(|s| {
+            s.feed_state
+                .queue
+                .iter()
+                .map(|e| e.title.as_str())
+                .collect::<Vec<&str>>()
+        })
+        .collect::<Vec<Vec<&str>>>();
+    assert_eq!(
+        vec![
+            vec!["testname1-2", "testname1-3"],
+            vec!["testname2-2"],
+            vec!["testname3-1"],
+        ],
+        queued
+    );
+
+    apply_global_backlog_limit(&mut subs, 0);
+    assert!(subs.iter().all(|s| s.feed_state.queue.is_empty()));
+}
+
+#[test]
+fn compact_test() {
+    let mut sub = Subscription::new("testurl", "testname", None);
+    for i in 1..3 {
+        sub.feed_state.entries.insert(
+            0,
+            Entry {
+                title: format!("testentry{}", i),
+                urls: vec![format!("testentryurl{}", i)],
+            },
+        );
+    }
+    sub.feed_state.latest_entry_number = 2;
+    sub.feed_state.queue.push(sub.feed_state.entries[0].clone());
+    sub.feed_state.summary_queue.push(SummaryEntry {
+        is_this_session: false,
+        number: 1,
+        name: "testentry1".to_string(),
+    });
+
+    let mut subs = vec![sub];
+    compact(&mut subs);
+
+    let sub = &mut subs[0];
+    assert!(sub.feed_state.summary_queue.is_empty());
+    assert_eq!("testentry2", sub.feed_state.entries[0].title);
+    assert_eq!("", sub.feed_state.entries[1].title);
+    assert_eq!(vec!["testentryurl1"], sub.feed_state.entries[1].urls);
+
+    // The feed hands back the compacted episode as if it were new.
+    sub.feed_state.entries.insert(
+        0,
+        Entry {
+            title: "testentry1".to_string(),
+            urls: vec!["testentryurl1".to_string()],
+        },
+    );
+    sub.feed_state.latest_entry_number = 3;
+
+    assert_eq!(1, sub.dedupe_entries());
+    assert_eq!(2, sub.feed_state.entries.len());
+}
+
+#[test]
+fn sync_plan_test() {
+    let mut shared = Subscription::new("https://example.com/feed", "testname1", None);
+    for i in 1..3 {
+        shared.feed_state.entries.insert(
+            0,
+            Entry {
+                title: format!("testentry{}", i),
+                urls: vec![format!("testentryurl{}", i)],
+            },
+        );
+    }
+    shared.feed_state.latest_entry_number = 2;
+    let local = vec![shared.clone()];
+
+    // The other device fetched one more entry and listened to entry 2.
+    let mut theirs = shared;
+    theirs.feed_state.entries.insert(
+        0,
+        Entry {
+            title: "testentry3".to_string(),
+            urls: vec!["testentryurl3".to_string()],
+        },
+    );
+    theirs.feed_state.latest_entry_number = 3;
+    theirs.feed_state.summary_queue.push(SummaryEntry {
+        is_this_session: true,
+        number: 2,
+        name: "testentry2".to_string(),
+    });
+    let extra = Subscription::new("https://example.com/other", "testname2", None);
+    let remote = vec![extra.clone(), theirs];
+
+    let plan = sync_plan(&local, &remote);
+
+    assert_eq!(vec![extra], plan.new_subscriptions);
+    assert_eq!(1, plan.new_entries.len());
+    assert_eq!(
+        (0, "testentry3"),
+        (plan.new_entries[0].0, plan.new_entries[0].1.title.as_str())
+    );
+    assert_eq!(1, plan.newly_listened.len());
+    assert_eq!(
+        (0, "testentry2"),
+        (
+            plan.newly_listened[0].0,
+            plan.newly_listened[0].1.title.as_str()
+        )
+    );
+
+    let mut merged = local.clone();
+    apply_sync_plan(&mut merged, &plan);
+
+    assert_eq!(2, merged.len());
+    assert_eq!("testentry3", merged[0].get_latest_entry_name());
+    assert_eq!(3, merged[0].get_latest_entry_number());
+    let listened = merged[0]
+        .listened_entries()
+        .into_iter()
+        .map(|e| (e.number, e.title))
+        .collect::<Vec<(u64, String)>>();
+    assert_eq!(vec![(2, "testentry2".to_string())], listened);
+    assert_eq!(SyncPlan::default(), sync_plan(&merged, &remote));
+}
+
+#[test]
+fn rebase_directories_test() {
+    let mut subs = vec![
+        Subscription::new("testurl1", "testname1", Some("/mnt/old/pods")),
+        Subscription::new("testurl2", "testname2", Some("/mnt/old/pods/news")),
+        Subscription::new("testurl3", "testname3", Some("/mnt/old/podcasts")),
+        Subscription::new("testurl4", "testname4", Some("rel/pods")),
+    ];
+    let original = subs.clone();
+
+    assert_eq!(0, rebase_directories(&mut subs, "", "/mnt/new"));
+    assert_eq!(0, rebase_directories(&mut subs, "/mnt/old", "/mnt/old"));
+    assert_eq!(original, subs);
+
+    assert_eq!(
+        2,
+        rebase_directories(&mut subs, "/mnt/old/pods", "/mnt/new")
+    );
+
+    assert_eq!("/mnt/new", subs[0].directory);
+    assert_eq!(
+        Path::new("/mnt/new").join("news").to_str().unwrap(),
+        subs[1].directory
+    );
+    assert_eq!("/mnt/old/podcasts", subs[2].directory);
+    assert_eq!("rel/pods", subs[3].directory);
+}
+
+#[test]
+fn scheme_mismatches_test() {
+    let mut upgraded = Subscription::new("http://example.com/feed", "testname1", None);
+    upgraded.url = "https://example.com/feed".to_string();
+    let mut moved_path = Subscription::new("http://example.com/feed", "testname2", None);
+    moved_path.url = "http://EXAMPLE.com/new-feed".to_string();
+    let mut moved_host = Subscription::new("http://example.com/feed", "testname3", None);
+    moved_host.url = "http://feeds.example.com/feed".to_string();
+
+    let subs = vec![
+        upgraded,
+        moved_path,
+        moved_host,
+        Subscription::new("testurl", "testname4", None),
+    ];
+
+    assert_eq!(vec![0, 2], scheme_mismatches(&subs));
+}
+
+#[test]
+fn opml_drift_test() {
+    let subs = vec![
+        Subscription::new("testurl1", "testname1", None),
+        Subscription::new("testurl2", "testname2", None),
+        Subscription::new("testurl3", "testname3", None),
+    ];
+
+    // Round trip first: the state's own export shows no drift.
+    let own = opml_drift(&subs, &to_opml_grouped(&subs)).unwrap();
+    assert_eq!(OpmlDrift::default(), own);
+
+    let opml = "<?xml version=\"1.0\"?>
+        <opml version=\"2.0\"><body>
+          <outline text=\"testname1\" xmlUrl=\"testurl1\"/>
+          <outline text=\"group\">
+            <outline text=\"testname3\" xmlUrl=\"testurl3-moved\"/>
+          </outline>
+          <outline title=\"testname4\" xmlUrl=\"testurl4\"/>
+        </body></opml>";
+
+    let drift = opml_drift(&subs, opml).unwrap();
+
+    assert_eq!(vec![1], drift.only_in_state);
+    assert_eq!(
+        vec![OpmlFeed {
+            name: "testname4".to_string(),
+            url: "testurl4".to_string(),
+        }],
+        drift.only_in_opml
+    );
+    assert_eq!(1, drift.mismatched.len());
+    assert_eq!(2, drift.mismatched[0].0);
+    assert_eq!("testurl3-moved", drift.mismatched[0].1.url);
+
+    match opml_drift(&subs, "<opml>") {
+        Err(SubscriptionError::InvalidOpml(_)) => (),
+        res => panic!("expected invalid opml, got {:?}", res),
+    }
+}
+
+#[test]
+fn to_opml_test() {
+    let subs = vec![
+        Subscription::new("testurl1", "testname1", None),
+        Subscription::new("testurl2?a=1&b=2", "test <name> 2", None),
+    ];
+
+    let opml = to_opml(&subs);
+
+    assert!(opml.starts_with("<?xml"));
+    assert!(opml.contains("xmlUrl=\"testurl1\""));
+    assert!(opml.contains("xmlUrl=\"testurl2?a=1&amp;b=2\""));
+    assert!(opml.contains("text=\"test &lt;name&gt; 2\""));
+    assert_eq!(2, opml.matches("<outline ").count());
+}
+
+#[test]
+fn to_opml_grouped_test() {
+    let subs = vec![
+        Subscription::new("testurl1", "testname1", Some("news")),
+        Subscription::new("testurl2", "testname2", Some("comedy")),
+        Subscription::new("testurl3", "testname3", Some("news")),
+        Subscription::new("testurl4", "testname4", None),
+        Subscription::new("testurl5", "testname5", Some(" ")),
+    ];
+
+    let opml = to_opml_grouped(&subs);
+
+    let lines: Vec<&str> = opml
+        .lines()
+        .skip_while(|l| l.trim() != "<body>")
+        .skip(1)
+        .take_while(|l| l.trim() != "</body>")
+        .map(|l| l.trim())
+        .collect();
+
+    let expected = vec![
+        "<outline type=\"rss\" text=\"testname4\" title=\"testname4\" xmlUrl=\"testurl4\"/>",
+        "<outline type=\"rss\" text=\"testname5\" title=\"testname5\" xmlUrl=\"testurl5\"/>",
+        "<outline text=\"news\" title=\"news\">",
+        "<outline type=\"rss\" text=\"testname1\" title=\"testname1\" xmlUrl=\"testurl1\"/>",
+        "<outline type=\"rss\" text=\"testname3\" title=\"testname3\" xmlUrl=\"testurl3\"/>",
+        "</outline>",
+        "<outline text=\"comedy\" title=\"comedy\">",
+        "<outline type=\"rss\" text=\"testname2\" title=\"testname2\" xmlUrl=\"testurl2\"/>",
+        "</outline>",
+    ];
+
+    assert_eq!(expected, lines);
+}
+
+#[test]
+fn file_deserialize_truncated_test() {
+    let test_path = "tmp_truncated_test.txt";
+
+    let subs = vec![Subscription::new("testurl", "testname", None)];
+    let s = vec_serialize(&subs);
+    File::create(test_path)
+        .unwrap()
+        .write_all(&s[..s.len() - 1])
+        .unwrap();
+
+    let res = std::panic::catch_unwind(|| file_deserialize(test_path));
+
+    fs::remove_file(test_path).unwrap();
+
+    let why = res.unwrap_err();
+    let message = why.downcast_ref::<String>().unwrap();
+    assert!(message.contains("truncated"), "{}", message);
+}
+
+#[test]
+fn file_serialize_gz_test() {
+    let plain_path = "tmp_gz_test.txt";
+    let gz_path = "tmp_gz_test.txt.gz";
+
+    // Get a store big enough for compression to pay off.
+    let mut subs = Vec::new();
+    for i in 0..50 {
+        let mut sub = Subscription::new(
+            &format!("https://example.com/feed{}.xml", i),
+            &format!("testname{}", i),
+            Some("testdir"),
+        );
+        sub.feed_state.entries.push(Entry {
+            title: format!("episode {}", i),
+            urls: vec![format!("https://example.com/episode{}.mp3", i)],
+        });
+        subs.push(sub);
+    }
+
+    match File::create(plain_path) {
+        Ok(mut file) => file.write_all(vec_serialize(&subs).as_slice()).unwrap(),
+        Err(why) => panic!("couldn't create {}: {}", plain_path, why),
+    };
+    file_serialize_gz(gz_path, &subs);
+
+    assert_eq!(subs, file_deserialize_gz(gz_path).unwrap());
+    // Extension detection goes through the same path.
+    assert_eq!(subs, file_deserialize(gz_path).unwrap());
+
+    let plain_len = fs::metadata(plain_path).unwrap().len();
+    let gz_len = fs::metadata(gz_path).unwrap().len();
+    assert!(gz_len < plain_len);
+
+    fs::remove_file(plain_path).unwrap();
+    fs::remove_file(gz_path).unwrap();
+}
+
+#[test]
+fn new_checked_test() {
+    let sub = Subscription::new_checked(" https://example.com/feed.xml ", "testname", None);
+    assert_eq!("https://example.com/feed.xml", sub.unwrap().url);
+
+    let url = FeedUrl::parse("http://user@Example.com:8080/feed").unwrap();
+    assert_eq!("http://user@Example.com:8080/feed", url.as_str());
+
+    for bad in &[
+        "",
+        "testurl",
+        "ftp://example.com/feed",
+        "https://",
+        "https:///feed",
+    ] {
+        match Subscription::new_checked(bad, "testname", None) {
+            Err(SubscriptionError::InvalidUrl(url)) => assert_eq!(bad.to_string(), url),
+            res => panic!("expected invalid url error, got {:?}", res),
+        }
+    }
+}
+
+#[test]
+fn id_test() {
+    let sub = Subscription::new("https://example.com/Feed.xml", "testname", None);
+    let mut redirected = Subscription::new("HTTPS://Example.com/Feed.xml/", "othername", None);
+    redirected.url = "https://cdn.example.com/feed.xml".to_string();
+    let other = Subscription::new("https://example.com/feed.xml", "testname", None);
+
+    assert_eq!(16, sub.id().len());
+    assert_eq!(sub.id(), redirected.id());
+    assert!(sub.id() != other.id());
+    // Pinned so a change to the hash shows up as a test failure.
+    assert_eq!(
+        "af63dc4c8601ec8c",
+        Subscription::new("a", "testname", None).id()
+    );
+}
+
+#[test]
+fn same_config_test() {
+    let sub = Subscription::new("testurl", "testname", Some("testdir"));
+    let mut fetched = sub.clone();
+    fetched.feed_state.entries.push(Entry {
+        title: "testentry".to_string(),
+        urls: Vec::new(),
+    });
+    fetched.feed_state.latest_entry_number = 1;
+
+    assert!(sub != fetched);
+    assert!(sub.same_config(&fetched));
+
+    let mut renamed = sub.clone();
+    renamed.name = "othername".to_string();
+    assert!(!sub.same_config(&renamed));
+}
+
+#[test]
+fn unlistened_count_test() {
+    let mut sub = Subscription::new("testurl", "testname", None);
+    // Two entries share a title; only the first has been listened to.
+    for (i, title) in ["testentry1", "testentry1", "testentry2"]
+        .iter()
+        .enumerate()
+    {
+        let entry = Entry {
+            title: title.to_string(),
+            urls: vec![format!("testentryurl{}", i)],
+        };
+        sub.feed_state.entries.insert(0, entry.clone());
+        sub.feed_state.queue.push(entry);
+    }
+    sub.feed_state.summary_queue.push(SummaryEntry {
+        is_this_session: true,
+        number: 1,
+        name: "testentry1".to_string(),
+    });
+
+    assert_eq!(2, sub.unlistened_count());
+}
+
+#[test]
+fn import_existing_files_test() {
+    let test_dir = "tmp_import_existing_files_test";
+    fs::create_dir_all(test_dir).unwrap();
+
+    let mut sub = Subscription::new("testurl", "testname", Some(test_dir));
+    for i in 1..3 {
+        let entry = Entry {
+            title: format!("testentry{}", i),
+            urls: vec![format!("https://example.com/ep{}.mp3", i)],
+        };
+        sub.feed_state.entries.insert(0, entry.clone());
+        sub.feed_state.queue.push(entry);
+    }
+
+    File::create(Path::new(test_dir).join("ep1.mp3")).unwrap();
+    File::create(Path::new(test_dir).join("unrelated.mp3")).unwrap();
+
+    let imported = sub.import_existing_files().unwrap();
+
+    fs::remove_dir_all(test_dir).unwrap();
+
+    assert_eq!(1, imported);
+    assert_eq!(1, sub.feed_state.queue.len());
+    assert_eq!("testentry2", sub.feed_state.queue[0].title);
+    assert_eq!(2, sub.feed_state.entries.len());
+    assert_eq!(0, sub.import_existing_files().unwrap());
+}
+
+#[test]
+fn requeue_all_test() {
+    let mut sub = Subscription::new("testurl", "testname", None);
+    for i in (1..4).rev() {
+        sub.feed_state.entries.push(Entry {
+            title: format!("testentry{}", i),
+            urls: vec![format!("testentryurl{}", i)],
+        });
+    }
+    sub.feed_state.queue.push(sub.feed_state.entries[0].clone());
+
+    sub.requeue_all();
+
+    let queue = &sub.feed_state.queue;
+    assert_eq!(sub.feed_state.entries.len(), queue.len());
+    for (i, entry) in queue.iter().enumerate() {
+        assert!(!queue[i + 1..].contains(entry));
+    }
+    assert_eq!("testentry3", queue[0].title);
+    assert_eq!("testentry1", queue[1].title);
+}
+
+#[test]
+fn clone_to_directory_test() {
+    let mut sub = Subscription::new("testurl", "testname", Some("testdir"));
+    for i in 1..3 {
+        sub.feed_state.entries.insert(
+            0,
+            Entry {
+                title: format!("testentry{}", i),
+                urls: vec![format!("testentryurl{}", i)],
+            },
+        );
+    }
+    let original = sub.clone();
+
+    let archive = sub.clone_to_directory("archivedir");
+
+    assert_eq!(original, sub);
+    assert_eq!("archivedir", archive.directory);
+    assert_eq!(sub.feed_state.entries, archive.feed_state.entries);
+    assert_eq!(2, archive.feed_state.queue.len());
+}
+
+#[test]
+fn dedupe_entries_test() {
+    let mut sub = Subscription::new("testurl", "testname", None);
+    let newest = Entry {
+        title: "testentry3".to_string(),
+        urls: vec!["testentryurl3".to_string()],
+    };
+    let fuller_dupe = Entry {
+        title: "testentry1 (fixed)".to_string(),
+        urls: vec!["testentryurl1".to_string(), "testentrymirror1".to_string()],
+    };
+    let oldest = Entry {
+        title: "testentry1".to_string(),
+        urls: vec!["testentryurl1".to_string()],
+    };
+    sub.feed_state.entries = vec![newest.clone(), fuller_dupe.clone(), oldest.clone()];
+    sub.feed_state.latest_entry_number = 3;
+    sub.feed_state.queue = vec![fuller_dupe.clone(), oldest.clone()];
+    sub.feed_state.summary_queue.push(SummaryEntry {
+        is_this_session: false,
+        number: 3,
+        name: "testentry3".to_string(),
+    });
+    sub.feed_state.summary_queue.push(SummaryEntry {
+        is_this_session: true,
+        number: 2,
+        name: "testentry1 (fixed)".to_string(),
+    });
+
+    assert_eq!(1, sub.dedupe_entries());
+
+    // The fuller copy survives under the lowest number.
+    assert_eq!(vec![newest, fuller_dupe.clone()], sub.feed_state.entries);
+    assert_eq!(vec![fuller_dupe], sub.feed_state.queue);
+    assert_eq!(2, sub.get_latest_entry_number());
+
+    let summary_numbers: Vec<u64> = sub
+        .feed_state
+        .summary_queue
+        .iter()
+        .map(|s| s.number)
+        .collect();
+    assert_eq!(vec![2, 1], summary_numbers);
+    assert_eq!("testentry1 (fixed)", sub.feed_state.summary_queue[1].name);
+
+    assert_eq!(0, sub.dedupe_entries());
+}
+
+#[test]
+fn dedupe_entries_misnumbered_summary_test() {
+    let mut sub = Subscription::new("testurl", "testname", None);
+    for &(title, url) in &[
+        ("testentry2", "testentryurl2"),
+        ("testentry1", "testentryurl1"),
+        ("testentry1", "testentryurl1"),
+    ] {
+        sub.feed_state.entries.push(Entry {
+            title: title.to_string(),
+            urls: vec![url.to_string()],
+        });
+    }
+    sub.feed_state.latest_entry_number = 3;
+    // Number 2 holds testentry1, so this summary is already off.
+    sub.feed_state.summary_queue.push(SummaryEntry {
+        is_this_session: false,
+        number: 2,
+        name: "testentry2".to_string(),
+    });
+
+    assert_eq!(1, sub.dedupe_entries());
+
+    assert_eq!(1, sub.feed_state.summary_queue.len());
+    assert_eq!("testentry2", sub.feed_state.summary_queue[0].name);
+}
+
+#[test]
+fn to_rss_test() {
+    let mut sub = Subscription::new("https://example.com/feed", "test & name", None);
+    sub.feed_state.entries.push(Entry {
+        title: "testentry <2>".to_string(),
+        urls: vec!["https://example.com/ep2.m4a?a=1&b=2".to_string()],
+    });
+    sub.feed_state.entries.push(Entry {
+        title: "".to_string(),
+        urls: Vec::new(),
+    });
+
+    let rss = sub.to_rss();
+    let doc = roxmltree::Document::parse(&rss).unwrap();
+
+    let channel = doc
+        .descendants()
+        .find(|n| n.has_tag_name("channel"))
+        .unwrap();
+    let channel_title = channel.children().find(|n| n.has_tag_name("title"));
+    assert_eq!(Some("test & name"), channel_title.unwrap().text());
+
+    let items: Vec<_> = doc
+        .descendants()
+        .filter(|n| n.has_tag_name("item"))
+        .collect();
+    let titles: Vec<&str> = items
+        .iter()
+        .map(|i| i.children().find(|n| n.has_tag_name("title")).unwrap())
+        .map(|t| t.text().unwrap())
+        .collect();
+    assert_eq!(vec!["testentry <2>", "Episode 1"], titles);
+
+    let enclosure = items[0]
+        .children()
+        .find(|n| n.has_tag_name("enclosure"))
+        .unwrap();
+    assert_eq!(
+        Some("https://example.com/ep2.m4a?a=1&b=2"),
+        enclosure.attribute("url")
+    );
+    assert_eq!(Some("audio/mp4"), enclosure.attribute("type"));
+    assert!(items[1].children().all(|n| !n.has_tag_name("enclosure")));
+}
+
+#[test]
+fn mime_breakdown_test() {
+    let mut sub = Subscription::new("testurl", "testname", None);
+    for url in &["ep3.mp3", "ep2.MP3?x=1", "ep1.mp4", "ep0"] {
+        sub.feed_state.entries.push(Entry {
+            title: "testentry".to_string(),
+            urls: vec![format!("https://example.com/{}", url)],
+        });
+    }
+    sub.feed_state.entries.push(Entry::default());
+
+    let breakdown = sub.mime_breakdown();
+
+    assert_eq!(3, breakdown.len());
+    assert_eq!(Some(&2), breakdown.get("audio/mpeg"));
+    assert_eq!(Some(&1), breakdown.get("video/mp4"));
+    assert_eq!(Some(&2), breakdown.get("unknown"));
+}
+
+#[test]
+fn session_stats_test() {
+    let mut sub = Subscription::new("testurl", "testname", None);
+    for &(number, is_this_session) in &[(3, true), (2, false), (1, false)] {
+        sub.feed_state.summary_queue.push(SummaryEntry {
+            is_this_session,
+            number,
+            name: format!("testentry{}", number),
+        });
+    }
+
+    let stats = sub.session_stats();
+
+    assert_eq!(1, stats.this_session);
+    assert_eq!(2, stats.prior_sessions);
+    assert_eq!(
+        SessionStats::default(),
+        Subscription::new("testurl", "testname", None).session_stats()
+    );
+}
+
+#[test]
+fn playback_order_test() {
+    let mut sub = Subscription::new("testurl", "testname", None);
+    for i in (1..4).rev() {
+        sub.feed_state.entries.push(Entry {
+            title: format!("testentry{}", i),
+            urls: Vec::new(),
+        });
+    }
+    let orphan = Entry {
+        title: "orphan".to_string(),
+        urls: Vec::new(),
+    };
+    sub.feed_state.queue.push(sub.feed_state.entries[1].clone());
+    sub.feed_state.queue.push(orphan);
+    sub.feed_state.queue.push(sub.feed_state.entries[0].clone());
+    sub.feed_state.queue.push(sub.feed_state.entries[2].clone());
+
+    let order = sub.playback_order();
+
+    let mut expected = sub.get_entries();
+    expected.reverse();
+    assert_eq!(expected[..], order[..3]);
+    assert_eq!("orphan", order[3].title);
+}
+
+#[test]
+fn search_test() {
+    let mut sub = Subscription::new("testurl", "testname", None);
+    for title in &[
+        "Interview: Guest",
+        "Bonus episode",
+        "interview: Another guest",
+    ] {
+        sub.feed_state.entries.push(Entry {
+            title: title.to_string(),
+            urls: Vec::new(),
+        });
+    }
+
+    let titles = |pattern| {
+        sub.search(pattern)
+            .unwrap()
+            .into_iter()
+            .map(|e| e.title)
+            .collect::<Vec<String>>()
+    };
+
+    assert_eq!(vec!["Interview: Guest"], titles("^Interview"));
+    assert_eq!(
+        vec!["Interview: Guest", "interview: Another guest"],
+        titles("(?i)^interview")
+    );
+
+    match sub.search("(unclosed") {
+        Err(SubscriptionError::InvalidPattern(_)) => (),
+        res => panic!("expected invalid pattern, got {:?}", res),
+    }
+}
+
+#[test]
+fn reconcile_summary_test() {
+    let mut sub = Subscription::new("testurl", "testname", None);
+    for title in &["Episode 2: Finale", "Episode 1: Pilot", "Episode 1: Pilot"] {
+        sub.feed_state.entries.push(Entry {
+            title: title.to_string(),
+            urls: Vec::new(),
+        });
+    }
+    for &(number, name) in &[
+        (7, "Episode 2: Finale"),
+        (1, "Episode 1: Pilot"),
+        (5, "episode 1 - pilot"),
+        (3, "Episode 0: Trailer"),
+        (9, "Episode 2: Finale"),
+    ] {
+        sub.feed_state.summary_queue.push(SummaryEntry {
+            is_this_session: false,
+            number,
+            name: name.to_string(),
+        });
+    }
+
+    let orphaned = sub.reconcile_summary();
+
+    assert_eq!(vec![(3, "Episode 0: Trailer".to_string())], orphaned);
+    let linked = sub
+        .feed_state
+        .summary_queue
+        .iter()
+        .map(|s| (s.number, s.name.as_str()))
+        .collect::<Vec<(u64, &str)>>();
+    assert_eq!(
+        vec![
+            (3, "Episode 2: Finale"),
+            (1, "Episode 1: Pilot"),
+            (2, "Episode 1: Pilot"),
+        ],
+        linked
+    );
+    assert_eq!(3, sub.session_stats().prior_sessions);
+}
+
+#[test]
+fn detect_dropped_test() {
+    let mut sub = Subscription::new("testurl", "testname", None);
+    for i in (1..4).rev() {
+        sub.feed_state.entries.push(Entry {
+            title: format!("testentry{}", i),
+            urls: vec![format!("testentryurl{}", i)],
+        });
+    }
+
+    // The feed now carries only the two newest, one under a new title.
+    let mut fresh = sub.get_entries();
+    fresh.truncate(2);
+    fresh[0].title = "testentry3 (updated)".to_string();
+
+    let dropped = sub.detect_dropped(&fresh);
+
+    assert_eq!(1, dropped.len());
+    assert_eq!(1, dropped[0].number);
+    assert_eq!("testentry1", dropped[0].title);
+}
+
+#[test]
+fn changed_entries_test() {
+    let mut sub = Subscription::new("testurl", "testname", None);
+    for i in (1..4).rev() {
+        sub.feed_state.entries.push(Entry {
+            title: format!("testentry{}", i),
+            urls: vec![format!("testentryurl{}", i)],
+        });
+    }
+
+    let mut fresh = sub.get_entries();
+    assert!(sub.changed_entries(&fresh).is_empty());
+
+    fresh[1].urls = vec!["testentryurl2-fixed".to_string()];
+    fresh.push(EntryView {
+        number: 4,
+        title: "testentry4".to_string(),
+        urls: Vec::new(),
+    });
+
+    assert_eq!(vec![2], sub.changed_entries(&fresh));
+}
+
+#[test]
+fn partition_by_test() {
+    let mut sub = Subscription::new("testurl", "testname", Some("testdir"));
+    for title in &["b: two", "a: two", "b: one", "a: one"] {
+        sub.feed_state.entries.push(Entry {
+            title: title.to_string(),
+            urls: Vec::new(),
+        });
+    }
+    sub.feed_state.latest_entry_number = 4;
+    sub.feed_state.queue.push(sub.feed_state.entries[1].clone());
+    sub.feed_state.summary_queue.push(SummaryEntry {
+        is_this_session: true,
+        number: 2,
+        name: "b: one".to_string(),
+    });
+
+    let parts = sub.partition_by(|e| e.title.split(':').next().unwrap().to_string());
+
+    assert_eq!(2, parts.len());
+
+    let b = &parts[0];
+    assert_eq!("testname (b)", b.name);
+    assert_eq!(
+        Path::new("testdir").join("b").to_str().unwrap(),
+        b.directory
+    );
+    assert_eq!(sub.url, b.url);
+    let b_titles: Vec<String> = b.get_entries().into_iter().map(|e| e.title).collect();
+    assert_eq!(vec!["b: two", "b: one"], b_titles);
+    assert!(b.feed_state.queue.is_empty());
+    assert_eq!(1, b.feed_state.summary_queue[0].number);
+
+    let a = &parts[1];
+    assert_eq!("testname (a)", a.name);
+    assert_eq!(
+        Path::new("testdir").join("a").to_str().unwrap(),
+        a.directory
+    );
+    assert_eq!(2, a.get_latest_entry_number());
+    assert_eq!("a: two", a.feed_state.queue[0].title);
+    assert!(a.feed_state.summary_queue.is_empty());
+}
+
+#[test]
+fn downloaded_unlistened_test() {
+    let test_dir = "tmp_downloaded_unlistened_test";
+    fs::create_dir_all(test_dir).unwrap();
+
+    let mut sub = Subscription::new("testurl", "testname", Some(test_dir));
+    for i in 1..4 {
+        sub.feed_state.entries.insert(
+            0,
+            Entry {
+                title: format!("testentry{}", i),
+                urls: vec![format!("https://example.com/ep{}.mp3", i)],
+            },
+        );
+    }
+    sub.feed_state.summary_queue.push(SummaryEntry {
+        is_this_session: false,
+        number: 1,
+        name: "testentry1".to_string(),
+    });
+
+    // ep3 was never downloaded.
+    File::create(Path::new(test_dir).join("ep1.mp3")).unwrap();
+    File::create(Path::new(test_dir).join("ep2.mp3")).unwrap();
+
+    let unlistened = sub.downloaded_unlistened().unwrap();
+
+    fs::remove_dir_all(test_dir).unwrap();
+
+    assert_eq!(1, unlistened.len());
+    assert_eq!(2, unlistened[0].number);
+    assert_eq!("testentry2", unlistened[0].title);
+    assert!(sub.downloaded_unlistened().unwrap().is_empty());
+}
+
+#[test]
+fn untracked_files_test() {
+    let test_dir = "tmp_untracked_files_test";
+    fs::create_dir_all(test_dir).unwrap();
+
+    let mut sub = Subscription::new("testurl", "testname", Some(test_dir));
+    sub.feed_state.entries.push(Entry {
+        title: "testentry".to_string(),
+        urls: vec!["https://example.com/episodes/ep1.mp3".to_string()],
+    });
+
+    File::create(Path::new(test_dir).join("ep1.mp3")).unwrap();
+    File::create(Path::new(test_dir).join("stray.mp3")).unwrap();
+
+    let untracked = sub.untracked_files().unwrap();
+
+    fs::remove_dir_all(test_dir).unwrap();
+
+    assert_eq!(vec![Path::new(test_dir).join("stray.mp3")], untracked);
+    assert!(sub.untracked_files().unwrap().is_empty());
+}
+
+#[test]
+fn disk_usage_test() {
+    let test_dir = "tmp_disk_usage_test";
+    fs::create_dir_all(test_dir).unwrap();
+
+    let mut sub = Subscription::new("testurl", "testname", Some(test_dir));
+    sub.feed_state.entries.push(Entry {
+        title: "testentry".to_string(),
+        urls: vec!["https://example.com/episodes/ep1.mp3?source=feed".to_string()],
+    });
+
+    File::create(Path::new(test_dir).join("ep1.mp3"))
+        .unwrap()
+        .write_all(&[0; 100])
+        .unwrap();
+    File::create(Path::new(test_dir).join("unrelated.mp3"))
+        .unwrap()
+        .write_all(&[0; 50])
+        .unwrap();
+
+    let by_url = sub.disk_usage().unwrap();
+
+    sub.use_title_as_filename = Some(true);
+    File::create(Path::new(test_dir).join("testentry.mp3"))
+        .unwrap()
+        .write_all(&[0; 10])
+        .unwrap();
+    let by_title = sub.disk_usage().unwrap();
+
+    fs::remove_dir_all(test_dir).unwrap();
+
+    assert_eq!(100, by_url);
+    assert_eq!(10, by_title);
+
+    let missing = Subscription::new("testurl", "testname", Some("tmp_no_such_dir"));
+    assert_eq!(0, missing.disk_usage().unwrap());
+}
+
+#[test]
+fn partition_by_unsafe_key_test() {
+    let mut sub = Subscription::new("testurl", "testname", Some("testdir"));
+    for title in &["/etc", "../../escape", ".."] {
+        sub.feed_state.entries.push(Entry {
+            title: title.to_string(),
+            urls: Vec::new(),
+        });
+    }
+
+    let dirs = sub
+        .partition_by(|e| e.title.clone())
+        .into_iter()
+        .map(|p| p.directory)
+        .collect::<Vec<String>>();
+
+    let base = Path::new("testdir");
+    assert_eq!(
+        vec![
+            base.join("_etc").to_string_lossy().to_string(),
+            base.join(".._.._escape").to_string_lossy().to_string(),
+            base.join("_").to_string_lossy().to_string(),
+        ],
+        dirs
+    );
+}
+
+#[test]
+fn partition_by_colliding_keys_test() {
+    let mut sub = Subscription::new("testurl", "testname", Some("testdir"));
+    for title in &["a/b", "a_b", "", ".", "a_b_2"] {
+        sub.feed_state.entries.push(Entry {
+            title: title.to_string(),
+            urls: Vec::new(),
+        });
+    }
+
+    let dirs = sub
+        .partition_by(|e| e.title.clone())
+        .into_iter()
+        .map(|p| p.directory)
+        .collect::<Vec<String>>();
+
+    let expected = ["a_b", "a_b_2", "_", "__2", "a_b_2_2"]
+        .iter()
+        .map(|d| Path::new("testdir").join(d).to_string_lossy().to_string())
+        .collect::<Vec<String>>();
+    assert_eq!(expected, dirs);
+}
+
+#[test]
+fn sanitize_filename_test() {
+    let composed = "Caf\u{e9}: \u{212b}ngstr\u{f6}m";
+    let decomposed = "Cafe\u{301}: A\u{30a}ngstro\u{308}m";
+
+    assert!(composed != decomposed);
+    assert_eq!(sanitize_filename(composed), sanitize_filename(decomposed));
+    assert_eq!(
+        "Caf\u{e9}_ \u{c5}ngstr\u{f6}m",
+        sanitize_filename(decomposed)
+    );
+}
+
+#[test]
+fn load_default_test() {
+    let test_path = "tmp_load_default_test.txt";
+
+    let subs = vec![Subscription::new("testurl", "testname", None)];
+    File::create(test_path)
+        .unwrap()
+        .write_all(vec_serialize(&subs).as_slice())
+        .unwrap();
+
+    env::set_var(STATE_ENV_VAR, test_path);
+    let loaded = load_default();
+    env::set_var(STATE_ENV_VAR, "tmp_load_default_missing.txt");
+    let missing = load_default();
+    env::remove_var(STATE_ENV_VAR);
+
+    fs::remove_file(test_path).unwrap();
+
+    assert_eq!(subs, loaded.unwrap());
+    assert!(missing.unwrap().is_empty());
+}
+
+#[test]
+fn to_m3u_test() {
+    let test_dir = "tmp_to_m3u_test";
+    fs::create_dir_all(test_dir).unwrap();
+
+    let mut sub = Subscription::new("testurl", "testname", Some(test_dir));
+    sub.feed_state.entries.push(Entry {
+        title: "testentry2".to_string(),
+        urls: vec!["https://example.com/ep2.mp3".to_string()],
+    });
+    sub.feed_state.entries.push(Entry {
+        title: "testentry1".to_string(),
+        urls: vec!["https://example.com/ep1.mp3".to_string()],
+    });
+    sub.feed_state.entries.push(Entry {
+        title: "testentry0".to_string(),
+        urls: vec!["https://example.com/ep0.mp3".to_string()],
+    });
+    sub.feed_state.entries.push(Entry::default());
+    File::create(Path::new(test_dir).join("ep1.mp3")).unwrap();
+
+    // testentry0 was already listened to, and the url-less entry can't be
+    // played.
+    sub.feed_state.queue = vec![
+        sub.feed_state.entries[0].clone(),
+        sub.feed_state.entries[1].clone(),
+        sub.feed_state.entries[3].clone(),
+    ];
+
+    let m3u = sub.to_m3u();
+
+    fs::remove_dir_all(test_dir).unwrap();
+
+    let local = Path::new(test_dir).join("ep1.mp3");
+    let expected = vec![
+        "#EXTM3U",
+        "#EXTINF:-1,testentry1",
+        local.to_str().unwrap(),
+        "#EXTINF:-1,testentry2",
+        "https://example.com/ep2.mp3",
+    ];
+    assert_eq!(expected, m3u.lines().collect::<Vec<&str>>());
+}